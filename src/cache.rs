@@ -0,0 +1,58 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use rfmt::{Config, RfmtError};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// An entry is an empty file named by the key of a formatted source. Creating it is atomic, so the concurrent runs
+// never see a half written entry, and a lost race only costs one more format.
+pub fn contains(src: &str, config: &Config) -> bool {
+    entry(src, config).is_file()
+}
+
+pub fn insert(src: &str, config: &Config) {
+    let dir = cache_dir();
+    if fs::create_dir_all(&dir).is_ok() {
+        let _ = File::create(entry(src, config));
+    }
+}
+
+pub fn clear() -> Result<(), RfmtError> {
+    let dir = cache_dir();
+    match fs::remove_dir_all(&dir) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map_err(|e| RfmtError::io(&dir, e)),
+    }
+}
+
+fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("RFMT_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return PathBuf::from(dir).join("rfmt");
+    }
+    match env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(".cache").join("rfmt"),
+        None => env::temp_dir().join("rfmt"),
+    }
+}
+
+#[inline]
+fn entry(src: &str, config: &Config) -> PathBuf {
+    let config = config.to_toml();
+    let key = [VERSION, &config, src].iter().fold(FNV_OFFSET, |hash, s| fnv(hash, s));
+    cache_dir().join(format!("{:016x}", key))
+}
+
+// FNV-1a, stable across rust versions unlike `DefaultHasher`. The 0xff after every part can not be in a utf8 str.
+#[inline]
+fn fnv(hash: u64, s: &str) -> u64 {
+    s.bytes().chain(Some(0xff)).fold(hash, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME))
+}
@@ -0,0 +1,632 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use serde_json::json;
+use syntax::parse::{self, ParseSess, lexer::comments};
+use syntax::source_map::FilePathMapping;
+use syntax_pos::FileName;
+
+use rfmt::config::{self, ConfigResolver};
+use rfmt::diff;
+use rfmt::{Config, ErrorLoc, Formatted, RfmtError, Style};
+
+use crate::Opt;
+use crate::cache;
+use crate::git;
+use crate::walk;
+
+macro_rules! p {
+    () => ({println!()});
+    ($arg:expr) => ({println!("{}", $arg)});
+    ($fmt:expr, $($arg:tt)*) => ({println!($fmt, $($arg)*)});
+    ($($arg:tt)+) => ({println!("{}", $($arg)+)});
+}
+
+macro_rules! d {
+    ($arg:expr) => ({println!("{:#?}", $arg)});
+}
+
+// Like `p`, but to the output of a file, which is printed when the file is done.
+macro_rules! w {
+    ($out:expr, $($arg:tt)+) => ({
+        $out.push_str(&format!($($arg)+));
+        $out.push('\n');
+    });
+}
+
+// `input` of `--diff-filter` to read the diff from stdin.
+const STDIN_INPUT: &str = "-";
+
+const SEP: &str = r#"
+------------------------------------------------------------------------------------------------------------------------
+"#;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emit {
+    Stdout,
+    Files,
+    Diff,
+    EditsJson,
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Emit, String> {
+        match s {
+            "stdout" => Ok(Emit::Stdout),
+            "files" => Ok(Emit::Files),
+            "diff" => Ok(Emit::Diff),
+            "edits-json" => Ok(Emit::EditsJson),
+            _ => Err(format!("unknown emit mode `{}`, expected one of `stdout`, `files`, `diff`, `edits-json`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    fn enabled(self) -> bool {
+        match self {
+            Color::Auto => atty::is(atty::Stream::Stdout),
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Color, String> {
+        match s {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!("unknown color mode `{}`, expected one of `auto`, `always`, `never`", s)),
+        }
+    }
+}
+
+// 1-based inclusive lines of a file, `path:start-end`.
+#[derive(Clone, Debug)]
+pub struct FileLines {
+    path: PathBuf,
+    start: usize,
+    end: usize,
+}
+
+impl FromStr for FileLines {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FileLines, String> {
+        let err = || format!("invalid lines `{}`, expected `file:start-end`", s);
+        let pos = s.rfind(':').ok_or_else(err)?;
+        let mut lines = s[pos + 1..].splitn(2, '-').map(|n| n.trim().parse::<usize>());
+        match (lines.next(), lines.next()) {
+            (Some(Ok(start)), Some(Ok(end))) if 0 < start && start <= end => Ok(FileLines {
+                path: PathBuf::from(&s[..pos]),
+                start,
+                end,
+            }),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl FileLines {
+    // The byte range of the lines in `src`, with the newline of the last one.
+    fn byte_range(&self, src: &str) -> Range<usize> {
+        let line_start = |line: usize| -> usize {
+            src.split('\n').take(line - 1).map(|line| line.len() + 1).sum::<usize>().min(src.len())
+        };
+        line_start(self.start)..line_start(self.end + 1)
+    }
+
+    fn is_for(&self, path: &Path) -> bool {
+        self.path == path || match (fs::canonicalize(&self.path), fs::canonicalize(path)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// A byte range of the input, `start..end`.
+#[derive(Clone, Debug)]
+pub struct ByteRange(Range<usize>);
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ByteRange, String> {
+        let mut bounds = s.splitn(2, "..").map(|n| n.trim().parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(start)), Some(Ok(end))) if start <= end => Ok(ByteRange(start..end)),
+            _ => Err(format!("invalid range `{}`, expected `start..end`", s)),
+        }
+    }
+}
+
+type Job = Result<(PathBuf, Config), RfmtError>;
+
+type Done = (Result<Status, RfmtError>, String);
+
+#[derive(PartialEq)]
+enum Status {
+    Formatted,
+    Unchanged,
+    CheckFailed,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    formatted: usize,
+    unchanged: usize,
+    failed: usize,
+    check_failed: usize,
+}
+
+impl Summary {
+    fn add(&mut self, result: Result<Status, RfmtError>) {
+        match result {
+            Ok(Status::Formatted) => self.formatted += 1,
+            Ok(Status::Unchanged) => self.unchanged += 1,
+            Ok(Status::CheckFailed) => self.check_failed += 1,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                self.failed += 1;
+            },
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.failed > 0 {
+            2
+        } else if self.check_failed > 0 {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} formatted, {} unchanged, {} failed, {} failing the check", self.formatted, self.unchanged,
+               self.failed, self.check_failed)
+    }
+}
+
+pub fn dump_ast(path: &PathBuf) -> Result<(), RfmtError> {
+    let src = read_file(path)?;
+
+    syntax::with_default_globals(|| {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = match parse::parse_crate_from_source_str(FileName::from(path.clone()), src.clone(), &sess) {
+            Ok(krate) => krate,
+            Err(mut e) => {
+                e.emit();
+                return;
+            },
+        };
+        d!(krate);
+
+        p!(SEP);
+
+        let cmnts = comments::gather_comments(&sess, FileName::from(path.clone()), src);
+        for cmnt in cmnts {
+            p!("{}: {:#?} {:#?}", cmnt.pos.0, cmnt.style, cmnt.lines);
+        }
+    });
+    Ok(())
+}
+
+pub fn fmt_from_stdin(opt: Opt) -> Result<Summary, RfmtError> {
+    let mut src = String::new();
+    let path = PathBuf::from("stdin");
+    io::stdin().read_to_string(&mut src).map_err(|e| RfmtError::io(&path, e))?;
+    let config = config::load_from_cwd(opt.style)?;
+
+    let mut out = String::new();
+    let result = fmt_str(src, &path, &opt, &config, &mut out);
+    print!("{}", out);
+
+    let mut summary = Summary::default();
+    summary.add(result);
+    Ok(summary)
+}
+
+pub fn print_config(input: &Option<PathBuf>, style: Option<Style>) -> Result<(), RfmtError> {
+    let config = match input {
+        Some(path) => config::load(path, style)?,
+        None => config::load_from_cwd(style)?,
+    };
+    print!("{}", config.to_toml());
+    Ok(())
+}
+
+pub fn import_config(input: &Option<PathBuf>) -> Result<(), RfmtError> {
+    let path = match input {
+        Some(path) => path.clone(),
+        None => {
+            let cwd = env::current_dir().unwrap_or_default();
+            match config::find_rustfmt_config(&cwd) {
+                Some(path) => path,
+                None => {
+                    let e = io::Error::new(io::ErrorKind::NotFound, "no rustfmt.toml or .rustfmt.toml found");
+                    return Err(RfmtError::io(&cwd, e));
+                },
+            }
+        },
+    };
+
+    let (file, _, warnings) = config::import_rustfmt_file(&path)?;
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    print!("{}", file.to_toml());
+    Ok(())
+}
+
+pub fn debug(path: &PathBuf, style: Option<Style>) -> Result<(), RfmtError> {
+    let src = read_file(path)?;
+    let (krate, cmnts) = rfmt::debug_ir(src, path, &config::load(path, style)?)?;
+
+    p!(krate);
+    p!(SEP);
+    p!(cmnts);
+    Ok(())
+}
+
+pub fn print(path: &PathBuf, style: Option<Style>) -> Result<(), RfmtError> {
+    let src = read_file(path)?;
+    p!(rfmt::print_ir(src, path, &config::load(path, style)?)?);
+    Ok(())
+}
+
+pub fn fmt(opt: Opt) -> Result<Summary, RfmtError> {
+    let path = match opt.input {
+        Some(ref path) => path.clone(),
+        None => {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, "no input file or dir");
+            return Err(RfmtError::io(&env::current_dir().unwrap_or_default(), e));
+        },
+    };
+    if opt.krate || path.is_dir() {
+        let files = if opt.krate {
+            rfmt::crate_files(&walk::crate_roots(&path)?)
+        } else {
+            walk::rs_files(&path, &opt)
+        };
+        let summary = fmt_files(files, Arc::new(opt));
+        eprintln!("{}", summary);
+        Ok(summary)
+    } else {
+        let config = config::load(&path, opt.style)?;
+        let mut summary = Summary::default();
+        summary.add(print_done(fmt_job(Ok((path, config)), &opt)));
+        Ok(summary)
+    }
+}
+
+// Formats only the lines touched by a diff, of `git diff`, or of stdin if `input` is `-`. Otherwise only the files in
+// `input` are formatted.
+pub fn fmt_diff_filter(mut opt: Opt) -> Result<Summary, RfmtError> {
+    let from_stdin = opt.input.as_ref().map_or(false, |input| input == Path::new(STDIN_INPUT));
+    let diff = if from_stdin {
+        let path = PathBuf::from("stdin");
+        let mut diff = String::new();
+        io::stdin().read_to_string(&mut diff).map_err(|e| RfmtError::io(&path, e))?;
+        diff
+    } else {
+        git::diff(opt.staged)?
+    };
+
+    let cwd = env::current_dir().unwrap_or_default();
+    let input = opt.input.as_ref().filter(|_| !from_stdin).map(|input| cwd.join(input));
+    let mut files = Vec::new();
+    for (path, lines) in diff::changed_lines(&diff) {
+        let path = PathBuf::from(path);
+        let is_rs = path.extension().map_or(false, |ext| ext == "rs");
+        let in_input = input.as_ref().map_or(true, |input| cwd.join(&path).starts_with(input));
+        if !is_rs || !in_input || lines.is_empty() {
+            continue;
+        }
+
+        opt.lines.extend(lines.into_iter().map(|(start, end)| FileLines {
+            path: path.clone(),
+            start,
+            end,
+        }));
+        files.push(Ok(path));
+    }
+
+    let summary = fmt_files(files, Arc::new(opt));
+    eprintln!("{}", summary);
+    Ok(summary)
+}
+
+fn fmt_files(files: Vec<Result<PathBuf, RfmtError>>, opt: Arc<Opt>) -> Summary {
+    let mut summary = Summary::default();
+    let mut resolver = ConfigResolver::new(opt.style);
+    let mut jobs = Vec::new();
+    for file in files {
+        jobs.push(file.and_then(|path| resolver.resolve(&path).map(|config| (path, config)).map_err(RfmtError::from)));
+    }
+
+    // A panic is reported as the error of its file by `fmt_job`, not printed when it happens.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let threads = cmp::min(opt.jobs, jobs.len());
+    if threads <= 1 {
+        for job in jobs {
+            summary.add(print_done(fmt_job(job, &opt)));
+        }
+    } else {
+        fmt_jobs_parallel(jobs, opt, threads, &mut summary);
+    }
+    panic::set_hook(hook);
+    summary
+}
+
+// Every worker formats the files in its own parser globals, the outputs are printed in the order of the jobs.
+fn fmt_jobs_parallel(jobs: Vec<Job>, opt: Arc<Opt>, threads: usize, summary: &mut Summary) {
+    let count = jobs.len();
+    let jobs = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..threads {
+        let jobs = jobs.clone();
+        let opt = opt.clone();
+        let tx = tx.clone();
+        thread::spawn(move || loop {
+            let job = jobs.lock().unwrap().next();
+            match job {
+                Some((idx, job)) => {
+                    if tx.send((idx, fmt_job(job, &opt))).is_err() {
+                        break;
+                    }
+                },
+                None => break,
+            }
+        });
+    }
+    drop(tx);
+
+    let mut done: BTreeMap<usize, Done> = BTreeMap::new();
+    let mut next = 0;
+    for (idx, result) in rx {
+        done.insert(idx, result);
+        while let Some(result) = done.remove(&next) {
+            summary.add(print_done(result));
+            next += 1;
+        }
+    }
+
+    // A worker panicked, its file is counted as failed.
+    let printed = next + done.len();
+    for (_, result) in done {
+        summary.add(print_done(result));
+    }
+    summary.failed += count - printed;
+}
+
+fn fmt_job(job: Job, opt: &Opt) -> Done {
+    let mut out = String::new();
+    let result = job.and_then(|(path, config)| {
+        match panic::catch_unwind(AssertUnwindSafe(|| fmt_file(&path, opt, &config, &mut out))) {
+            Ok(result) => result,
+            Err(e) => {
+                out.clear();
+                let msg = e.downcast_ref::<&str>().map(|msg| msg.to_string())
+                           .or_else(|| e.downcast_ref::<String>().cloned())
+                           .unwrap_or_default();
+                Err(RfmtError::Panic(ErrorLoc::file(&path), msg))
+            },
+        }
+    });
+    (result, out)
+}
+
+#[inline]
+fn print_done(done: Done) -> Result<Status, RfmtError> {
+    print!("{}", done.1);
+    done.0
+}
+
+fn fmt_file(path: &PathBuf, opt: &Opt, config: &Config, out: &mut String) -> Result<Status, RfmtError> {
+    let src = if opt.staged {
+        let src = git::staged(path)?;
+        // The staged content is written to the file, it must not drop the unstaged changes.
+        let writes = opt.emit() == Emit::Files && !opt.check && !opt.list_different;
+        if writes && read_file(path)? != src {
+            let e = io::Error::new(io::ErrorKind::Other, "the file has unstaged changes, it is not overwritten");
+            return Err(RfmtError::io(path, e));
+        }
+        src
+    } else {
+        read_file(path)?
+    };
+    fmt_str(src, path, opt, config, out)
+}
+
+fn fmt_str(src: String, path: &PathBuf, opt: &Opt, config: &Config, out: &mut String) -> Result<Status, RfmtError> {
+    if opt.verify_idempotent {
+        return verify_idempotent(src, path, opt, config, out);
+    }
+
+    let use_cache = config.cache && !opt.no_cache;
+    if use_cache && cache::contains(&src, config) {
+        if let (Emit::Stdout, false, false) = (opt.emit(), opt.check, opt.list_different) {
+            w!(out, "{}", src);
+        }
+        if let Some(cursor) = opt.cursor {
+            eprintln!("{}", cursor);
+        }
+        return Ok(Status::Unchanged);
+    }
+
+    let ranges = ranges(&src, path, opt);
+    let ft_result = match ranges {
+        Some(ref ranges) => rfmt::format_ranges(&src, path, config, ranges)?,
+        None => rfmt::format_str_with_path(&src, path, config)?,
+    };
+    if let Some(cursor) = opt.cursor {
+        eprintln!("{}", ft_result.source_map.map_offset(cursor));
+    }
+    let differs = ft_result.s != src;
+    if use_cache && ranges.is_none() && !differs && !ft_result.has_problems() {
+        cache::insert(&src, config);
+    }
+    // A check fails in every emit mode, so that `--check --diff` can gate a merge too.
+    let check = opt.check || opt.list_different;
+    let status = if check && (differs || ft_result.has_problems()) {
+        Status::CheckFailed
+    } else if differs {
+        Status::Formatted
+    } else {
+        Status::Unchanged
+    };
+
+    match opt.emit() {
+        Emit::Diff => {
+            let path = display_path(path, opt).to_string_lossy();
+            out.push_str(&diff::unified_diff(&src, &ft_result.s, &path, opt.color.enabled()));
+        },
+        Emit::EditsJson => {
+            if differs {
+                verify_output(&src, &ft_result, path, opt, config)?;
+                let edits = diff::text_edits(&src, &ft_result.s);
+                w!(out, "{}", json!({"file": display_path(path, opt).to_string_lossy(), "edits": edits}));
+            }
+        },
+        // A check never writes the files.
+        Emit::Stdout | Emit::Files if check => {
+            if status == Status::CheckFailed {
+                if opt.list_different {
+                    w!(out, "{}", path.display());
+                    return Ok(status);
+                }
+
+                w!(out, "{:?}", path);
+                if differs {
+                    w!(out, "not formatted");
+                }
+                for warning in &ft_result.warnings {
+                    w!(out, "warning: {}", warning);
+                }
+                if !ft_result.exceed_lines.is_empty() {
+                    w!(out, "exceed_lines: {:?}", ft_result.exceed_lines);
+                }
+                if !ft_result.trailing_ws_lines.is_empty() {
+                    w!(out, "trailing_ws_lines: {:?}", ft_result.trailing_ws_lines);
+                }
+                for &(line, ref cmnt) in &ft_result.lost_comments {
+                    w!(out, "lost comment at line {}: {}", line, cmnt);
+                }
+                w!(out, "{}", SEP);
+            }
+        },
+        Emit::Files => {
+            if differs {
+                verify_output(&src, &ft_result, path, opt, config)?;
+                let mut file = File::create(path).map_err(|e| RfmtError::io(path, e))?;
+                file.write_all(ft_result.s.as_bytes()).map_err(|e| RfmtError::io(path, e))?;
+            }
+        },
+        Emit::Stdout => w!(out, "{}", ft_result.s),
+    }
+    Ok(status)
+}
+
+// An output which loses comments or is not equivalent to `src` is neither written nor turned into edits.
+fn verify_output(src: &str, ft_result: &Formatted, path: &Path, opt: &Opt, config: &Config) -> Result<(), RfmtError> {
+    if !ft_result.lost_comments.is_empty() && !opt.allow_comment_loss {
+        return Err(RfmtError::CommentLoss(ErrorLoc::file(path), ft_result.lost_comments.clone()));
+    }
+    rfmt::verify_equivalent(src, &ft_result.s, path, config)
+}
+
+// The byte ranges to format of `src` with `--range` or `--lines`. A file without `--lines` has no range to format.
+fn ranges(src: &str, path: &Path, opt: &Opt) -> Option<Vec<Range<usize>>> {
+    if let Some(ByteRange(ref range)) = opt.range {
+        Some(vec![range.clone()])
+    } else if !opt.lines.is_empty() {
+        Some(opt.lines.iter().filter(|lines| lines.is_for(path)).map(|lines| lines.byte_range(src)).collect())
+    } else {
+        None
+    }
+}
+
+fn verify_idempotent(src: String, path: &PathBuf, opt: &Opt, config: &Config, out: &mut String)
+-> Result<Status, RfmtError> {
+    let first = rfmt::format_str_with_path(&src, path, config)?;
+    let second = rfmt::format_str_with_path(&first.s, path, config)?;
+    if let Some(line) = diff::first_diff_line(&first.s, &second.s) {
+        let path = display_path(path, opt).to_string_lossy();
+        w!(out, "{}: not idempotent, the second pass differs from line {}", path, line);
+        out.push_str(&diff::unified_diff_head(&first.s, &second.s, &path, opt.color.enabled(), 1));
+        return Ok(Status::CheckFailed);
+    }
+
+    if first.s == src {
+        Ok(Status::Unchanged)
+    } else {
+        Ok(Status::Formatted)
+    }
+}
+
+#[inline]
+fn display_path<'a>(path: &'a Path, opt: &Opt) -> &'a Path {
+    match opt.input {
+        Some(ref input) if input.is_dir() => path.strip_prefix(input).unwrap_or(path),
+        _ => path,
+    }
+}
+
+#[inline]
+fn read_file(path: &Path) -> Result<String, RfmtError> {
+    fs::read_to_string(path).map_err(|e| RfmtError::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use structopt::StructOpt;
+
+    use super::*;
+
+    fn format_ranges(args: &[&str], path: &str) -> String {
+        let opt = Opt::from_iter(args);
+        let path = Path::new(path);
+        let src = read_file(path).unwrap();
+        let ranges = ranges(&src, path, &opt).unwrap();
+        rfmt::format_ranges(&src, path, &Config::default(), &ranges).unwrap().s
+    }
+
+    #[test]
+    fn lines() {
+        let s = format_ranges(&["rfmt", "--lines", "tests/partial/lines.rs:2-2"], "tests/partial/lines.rs");
+        assert_eq!(s, include_str!("../tests/partial/lines.out.rs"));
+    }
+
+    #[test]
+    fn range() {
+        let s = format_ranges(&["rfmt", "--range", "20..21"], "tests/partial/range.rs");
+        assert_eq!(s, include_str!("../tests/partial/range.out.rs"));
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_FILE_NAME: &'static str = "rfmt.toml";
+pub const RUSTFMT_CONFIG_FILE_NAMES: [&'static str; 2] = ["rustfmt.toml", ".rustfmt.toml"];
+
+const MIN_MAX_WIDTH: usize = 20;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Config {
+    pub style: Style,
+    /// Lines longer than this are reported as exceed lines.
+    pub max_width: usize,
+    /// Wrapped lists are aligned to their open position only up to this column.
+    pub max_align_col: usize,
+    pub tab_spaces: usize,
+    pub wrap_indent: usize,
+    pub hard_tabs: bool,
+    pub newline_style: NewlineStyle,
+    pub max_blank_lines: usize,
+    pub reorder_imports: bool,
+    pub reorder_modules: bool,
+    /// Align a wrapped list to its open position, otherwise indent it by `wrap_indent`.
+    pub visual_align: bool,
+    /// Put every element of a list which can not be in one line in its own indented line.
+    pub block_indent: bool,
+    /// Add a trailing comma to a list laid out by `block_indent`.
+    pub trailing_comma: bool,
+    /// Remember the files that are already formatted, and skip them in the next runs.
+    pub cache: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::from_file(&ConfigFile::default().with_preset(None))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    Classic,
+    Rustfmt,
+    Compact,
+}
+
+impl Style {
+    // Every knob except the ones derived from others, like `max_align_col` from `max_width`.
+    fn preset(self) -> ConfigFile {
+        match self {
+            Style::Classic => ConfigFile {
+                max_width: Some(120),
+                tab_spaces: Some(4),
+                hard_tabs: Some(false),
+                newline_style: Some(NewlineStyle::Unix),
+                max_blank_lines: Some(1),
+                reorder_imports: Some(true),
+                reorder_modules: Some(true),
+                visual_align: Some(true),
+                block_indent: Some(false),
+                trailing_comma: Some(false),
+                cache: Some(false),
+                ..Default::default()
+            },
+            Style::Rustfmt => ConfigFile {
+                max_width: Some(100),
+                tab_spaces: Some(4),
+                wrap_indent: Some(4),
+                hard_tabs: Some(false),
+                newline_style: Some(NewlineStyle::Auto),
+                max_blank_lines: Some(1),
+                reorder_imports: Some(true),
+                reorder_modules: Some(true),
+                visual_align: Some(false),
+                block_indent: Some(true),
+                trailing_comma: Some(true),
+                cache: Some(false),
+                ..Default::default()
+            },
+            Style::Compact => ConfigFile {
+                max_width: Some(100),
+                tab_spaces: Some(2),
+                hard_tabs: Some(false),
+                newline_style: Some(NewlineStyle::Unix),
+                max_blank_lines: Some(1),
+                reorder_imports: Some(true),
+                reorder_modules: Some(true),
+                visual_align: Some(true),
+                block_indent: Some(false),
+                trailing_comma: Some(false),
+                cache: Some(false),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Style, String> {
+        match s {
+            "classic" => Ok(Style::Classic),
+            "rustfmt" => Ok(Style::Rustfmt),
+            "compact" => Ok(Style::Compact),
+            _ => Err(format!("unknown style `{}`, expected one of `classic`, `rustfmt`, `compact`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum NewlineStyle {
+    Unix,
+    Windows,
+    Native,
+    /// Use the newline of the first line of the input.
+    Auto,
+}
+
+impl NewlineStyle {
+    pub fn apply(self, src: &str, s: String) -> String {
+        let crlf = match self {
+            NewlineStyle::Unix => false,
+            NewlineStyle::Windows => true,
+            NewlineStyle::Native => cfg!(windows),
+            NewlineStyle::Auto => src.find('\n').map_or(cfg!(windows), |pos| src[..pos].ends_with('\r')),
+        };
+
+        if crlf {
+            s.replace("\r\n", "\n").replace('\n', "\r\n")
+        } else {
+            s
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Stop searching parent dirs for more config files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    style: Option<Style>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_width: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_align_col: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tab_spaces: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wrap_indent: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hard_tabs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    newline_style: Option<NewlineStyle>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_blank_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reorder_imports: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reorder_modules: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visual_align: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block_indent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trailing_comma: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<bool>,
+    /// Gitignore style patterns of the paths a dir walk skips, relative to the dir of this file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ignore: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path.display(), self.line, self.col, self.msg)
+    }
+}
+
+impl ConfigFile {
+    fn is_root(&self) -> bool {
+        self.root.unwrap_or(false)
+    }
+
+    fn merge(self, parent: &ConfigFile) -> ConfigFile {
+        ConfigFile {
+            root: self.root,
+            style: self.style.or(parent.style),
+            max_width: self.max_width.or(parent.max_width),
+            max_align_col: self.max_align_col.or(parent.max_align_col),
+            tab_spaces: self.tab_spaces.or(parent.tab_spaces),
+            wrap_indent: self.wrap_indent.or(parent.wrap_indent),
+            hard_tabs: self.hard_tabs.or(parent.hard_tabs),
+            newline_style: self.newline_style.or(parent.newline_style),
+            max_blank_lines: self.max_blank_lines.or(parent.max_blank_lines),
+            reorder_imports: self.reorder_imports.or(parent.reorder_imports),
+            reorder_modules: self.reorder_modules.or(parent.reorder_modules),
+            visual_align: self.visual_align.or(parent.visual_align),
+            block_indent: self.block_indent.or(parent.block_indent),
+            trailing_comma: self.trailing_comma.or(parent.trailing_comma),
+            cache: self.cache.or(parent.cache),
+            ignore: self.ignore,
+        }
+    }
+
+    // Fills the keys which are not set with the preset of `style`, or of the style of this config.
+    fn with_preset(self, style: Option<Style>) -> ConfigFile {
+        let style = style.or(self.style).unwrap_or(Style::Classic);
+        let mut file = self.merge(&style.preset());
+        file.style = Some(style);
+        file
+    }
+
+    // The keys which are set.
+    fn keys(&self) -> Vec<String> {
+        match toml::Value::try_from(self) {
+            Ok(toml::Value::Table(table)) => table.into_iter().map(|(key, _)| key).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+impl Config {
+    // The keys which are not set are the ones of the classic preset, the only defaults.
+    fn from_file(file: &ConfigFile) -> Config {
+        let file = file.clone().merge(&Style::Classic.preset());
+        let max_width = file.max_width.unwrap();
+        let tab_spaces = file.tab_spaces.unwrap();
+
+        Config {
+            style: file.style.unwrap_or(Style::Classic),
+            max_width,
+            max_align_col: file.max_align_col.unwrap_or(max_width / 3),
+            tab_spaces,
+            wrap_indent: file.wrap_indent.unwrap_or(tab_spaces * 2),
+            hard_tabs: file.hard_tabs.unwrap(),
+            newline_style: file.newline_style.unwrap(),
+            max_blank_lines: file.max_blank_lines.unwrap(),
+            reorder_imports: file.reorder_imports.unwrap(),
+            reorder_modules: file.reorder_modules.unwrap(),
+            visual_align: file.visual_align.unwrap(),
+            block_indent: file.block_indent.unwrap(),
+            trailing_comma: file.trailing_comma.unwrap(),
+            cache: file.cache.unwrap(),
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+// The config file which sets a key, its errors are reported there.
+struct KeySource {
+    path: PathBuf,
+    src: String,
+}
+
+// The config merged from the files of a dir and its parent dirs, and the file of every key it sets.
+#[derive(Clone, Default)]
+struct Resolved {
+    file: ConfigFile,
+    sources: HashMap<String, Rc<KeySource>>,
+}
+
+impl Resolved {
+    fn error(&self, dir: &Path, key: &str, msg: String) -> ConfigError {
+        let (path, line) = match self.sources.get(key) {
+            Some(source) => (source.path.clone(), key_line(&source.src, key)),
+            None => (dir.to_path_buf(), 0),
+        };
+        ConfigError {
+            path,
+            line,
+            col: 1,
+            msg,
+        }
+    }
+}
+
+// Resolves the config of every dir by merging its `rfmt.toml` over the configs of its parent dirs, until a config with
+// `root = true` or the file system root. Results are cached per dir.
+pub struct ConfigResolver {
+    style: Option<Style>,
+    cache: HashMap<PathBuf, Resolved>,
+}
+
+impl ConfigResolver {
+    // `style` overrides the style set in config files.
+    pub fn new(style: Option<Style>) -> ConfigResolver {
+        ConfigResolver {
+            style,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn resolve(&mut self, path: &Path) -> Result<Config, ConfigError> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            match path.parent() {
+                Some(dir) => dir,
+                None => return Ok(Config::from_file(&ConfigFile::default().with_preset(self.style))),
+            }
+        };
+
+        // The config which is used is validated, with the style override, and the preset of this style.
+        let resolved = self.resolve_dir(dir)?;
+        let file = resolved.file.clone().with_preset(self.style);
+        validate(&file).map_err(|(key, msg)| resolved.error(dir, key, msg))?;
+        Ok(Config::from_file(&file))
+    }
+
+    fn resolve_dir(&mut self, dir: &Path) -> Result<Resolved, ConfigError> {
+        if let Some(resolved) = self.cache.get(dir) {
+            return Ok(resolved.clone());
+        }
+
+        let config_path = dir.join(CONFIG_FILE_NAME);
+        let rustfmt_config_path = find_rustfmt_config(dir);
+        let resolved = if config_path.is_file() || rustfmt_config_path.is_some() {
+            let (config_path, file, src) = if config_path.is_file() {
+                let (file, src) = load_file(&config_path)?;
+                (config_path, file, src)
+            } else {
+                // Like rustfmt, the nearest rustfmt.toml is the only one used.
+                let config_path = rustfmt_config_path.unwrap();
+                let (mut file, src, warnings) = import_rustfmt_file(&config_path)?;
+                for warning in warnings {
+                    eprintln!("warning: {}", warning);
+                }
+                file.root = Some(true);
+                (config_path, file, src)
+            };
+            let mut resolved = match dir.parent() {
+                Some(parent) if !file.is_root() => self.resolve_dir(parent)?,
+                _ => Resolved::default(),
+            };
+            let source = Rc::new(KeySource {
+                path: config_path,
+                src,
+            });
+            for key in file.keys() {
+                resolved.sources.insert(key, source.clone());
+            }
+            resolved.file = file.merge(&resolved.file);
+            resolved
+        } else {
+            match dir.parent() {
+                Some(parent) => self.resolve_dir(parent)?,
+                None => Default::default(),
+            }
+        };
+
+        self.cache.insert(dir.to_path_buf(), resolved.clone());
+        Ok(resolved)
+    }
+}
+
+pub fn load(path: &Path, style: Option<Style>) -> Result<Config, ConfigError> {
+    ConfigResolver::new(style).resolve(path)
+}
+
+pub fn load_from_cwd(style: Option<Style>) -> Result<Config, ConfigError> {
+    let cwd = env::current_dir().unwrap_or_default();
+    load(&cwd, style)
+}
+
+pub fn find_rustfmt_config(dir: &Path) -> Option<PathBuf> {
+    RUSTFMT_CONFIG_FILE_NAMES.iter().map(|name| dir.join(name)).find(|path| path.is_file())
+}
+
+// The `ignore` patterns of the rfmt.toml in `dir` and the path of this file. They are not inherited like the other
+// keys, a dir walk applies them to the whole sub tree of `dir`. A rustfmt.toml has no `ignore` for rfmt.
+pub fn load_ignore(dir: &Path) -> Result<Option<(PathBuf, Vec<String>)>, ConfigError> {
+    let config_path = dir.join(CONFIG_FILE_NAME);
+    if !config_path.is_file() {
+        return Ok(None);
+    }
+    let (file, _) = load_file(&config_path)?;
+    Ok(file.ignore.map(|patterns| (config_path, patterns)))
+}
+
+// Maps the rustfmt options which have an rfmt equivalent, the others are returned as warnings.
+pub fn import_rustfmt_file(path: &Path) -> Result<(ConfigFile, String, Vec<ConfigError>), ConfigError> {
+    let src = read_file(path)?;
+    let table: toml::value::Table = toml::from_str(&src).map_err(|e| toml_error(path, e))?;
+
+    let mut file = ConfigFile::default();
+    let mut warnings = Vec::new();
+    for (key, value) in &table {
+        let key_error = |msg: String| ConfigError {
+            path: path.to_path_buf(),
+            line: key_line(&src, key),
+            col: 1,
+            msg,
+        };
+
+        match key.as_str() {
+            "max_width" => file.max_width = Some(import_value(key, value).map_err(key_error)?),
+            "tab_spaces" => file.tab_spaces = Some(import_value(key, value).map_err(key_error)?),
+            "hard_tabs" => file.hard_tabs = Some(import_value(key, value).map_err(key_error)?),
+            "newline_style" => file.newline_style = Some(import_value(key, value).map_err(key_error)?),
+            "reorder_imports" => file.reorder_imports = Some(import_value(key, value).map_err(key_error)?),
+            "reorder_modules" => file.reorder_modules = Some(import_value(key, value).map_err(key_error)?),
+            "blank_lines_upper_bound" => {
+                file.max_blank_lines = Some(import_value(key, value).map_err(key_error)?);
+            },
+            _ => warnings.push(key_error(format!("`{}` has no rfmt equivalent, ignored", key))),
+        }
+    }
+    Ok((file, src, warnings))
+}
+
+#[inline]
+fn import_value<T: DeserializeOwned>(key: &str, value: &toml::Value) -> Result<T, String> {
+    value.clone().try_into().map_err(|e| format!("invalid value for `{}`: {}", key, e))
+}
+
+fn read_file(path: &Path) -> Result<String, ConfigError> {
+    fs::read_to_string(path).map_err(|e| ConfigError {
+        path: path.to_path_buf(),
+        line: 0,
+        col: 0,
+        msg: e.to_string(),
+    })
+}
+
+fn toml_error(path: &Path, e: toml::de::Error) -> ConfigError {
+    let (line, col) = e.line_col().map_or((0, 0), |(line, col)| (line + 1, col + 1));
+    ConfigError {
+        path: path.to_path_buf(),
+        line,
+        col,
+        msg: e.to_string(),
+    }
+}
+
+fn load_file(path: &Path) -> Result<(ConfigFile, String), ConfigError> {
+    let src = read_file(path)?;
+    let file = toml::from_str(&src).map_err(|e| toml_error(path, e))?;
+    Ok((file, src))
+}
+
+fn validate(file: &ConfigFile) -> Result<(), (&'static str, String)> {
+    let config = Config::from_file(file);
+
+    if config.max_width < MIN_MAX_WIDTH {
+        return Err(("max_width", format!("`max_width` must be at least {}, found {}", MIN_MAX_WIDTH,
+                                         config.max_width)));
+    }
+    if config.max_align_col >= config.max_width {
+        return Err(("max_align_col", format!("`max_align_col` must be less than `max_width` ({}), found {}",
+                                             config.max_width, config.max_align_col)));
+    }
+    if config.tab_spaces == 0 {
+        return Err(("tab_spaces", "`tab_spaces` must be greater than 0".to_string()));
+    }
+    if config.wrap_indent >= config.max_width {
+        // The default `wrap_indent` comes from `tab_spaces`.
+        let key = if file.wrap_indent.is_some() { "wrap_indent" } else { "tab_spaces" };
+        return Err((key, format!("`wrap_indent` must be less than `max_width` ({}), found {}",
+                                 config.max_width, config.wrap_indent)));
+    }
+    Ok(())
+}
+
+fn key_line(src: &str, key: &str) -> usize {
+    src.lines().position(|line| {
+        let line = line.trim_start();
+        line.starts_with(key) && line[key.len()..].trim_start().starts_with('=')
+    }).map_or(0, |pos| pos + 1)
+}
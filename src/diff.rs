@@ -0,0 +1,477 @@
+use std::cmp;
+
+use serde::Serialize;
+
+const CONTEXT: usize = 3;
+
+const BOLD: &str = "\x1b[1m";
+const CYAN: &str = "\x1b[36m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+impl Edit {
+    #[inline]
+    fn is_equal(self) -> bool {
+        match self {
+            Edit::Equal(..) => true,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    fn old_len(self) -> usize {
+        match self {
+            Edit::Insert(..) => 0,
+            _ => 1,
+        }
+    }
+
+    #[inline]
+    fn new_len(self) -> usize {
+        match self {
+            Edit::Delete(..) => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// Replaces the 0-based lines `start..end` of the old text with `text`, which is whole lines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Replaces the text from `start_line:start_col` to `end_line:end_col` of the old text with `new_text`. Lines and
+/// columns are 1-based, the columns count chars, and the end is excluded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TextEdit {
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    pub new_text: String,
+}
+
+#[inline]
+fn lines(s: &str) -> Vec<&str> {
+    s.split_terminator('\n').collect()
+}
+
+#[inline]
+fn hunk_range(start: usize, len: usize) -> String {
+    if len == 0 {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, len)
+    }
+}
+
+// Myers' O(ND) diff, the `V` array of every round is kept for the backtrack.
+pub fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; (2 * max + 3) as usize];
+    let mut trace = Vec::new();
+
+    'outer: for d in 0..=max {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                break 'outer;
+            }
+            k += 2;
+        }
+    }
+
+    let mut edits = Vec::new();
+    let mut x = n;
+    let mut y = m;
+    for d in (0..trace.len() as isize).rev() {
+        if d == 0 {
+            while x > 0 && y > 0 {
+                x -= 1;
+                y -= 1;
+                edits.push(Edit::Equal(x as usize, y as usize));
+            }
+            break;
+        }
+
+        let v = &trace[d as usize];
+        let get = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if x == prev_x {
+            edits.push(Edit::Insert(prev_y as usize));
+        } else {
+            edits.push(Edit::Delete(prev_x as usize));
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+/// The line changes which turn `old` into `new`, in order. Only the lines which differ are in a change.
+pub fn line_changes(old: &str, new: &str) -> Vec<Change> {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let mut changes = Vec::new();
+    let mut change: Option<Change> = None;
+    let mut next_old = 0;
+    for edit in diff_lines(&old_lines, &new_lines) {
+        match edit {
+            Edit::Equal(x, _) => {
+                changes.extend(change.take());
+                next_old = x + 1;
+            },
+            Edit::Delete(x) => {
+                change.get_or_insert(Change {
+                    start: x,
+                    end: x,
+                    text: String::new(),
+                }).end = x + 1;
+                next_old = x + 1;
+            },
+            Edit::Insert(y) => {
+                let change = change.get_or_insert(Change {
+                    start: next_old,
+                    end: next_old,
+                    text: String::new(),
+                });
+                change.text.push_str(new_lines[y]);
+                change.text.push('\n');
+            },
+        }
+    }
+    changes.extend(change);
+    changes
+}
+
+/// The minimal edits which turn `old` into `new`, line by line, in order.
+pub fn text_edits(old: &str, new: &str) -> Vec<TextEdit> {
+    let old_lines = lines(old);
+    let mut edits: Vec<_> = line_changes(old, new).into_iter().map(|change| TextEdit {
+        start_line: change.start + 1,
+        start_col: 1,
+        end_line: change.end + 1,
+        end_col: 1,
+        new_text: change.text,
+    }).collect();
+
+    // The last line of `old` has no newline, so the end of `old` is at the end of this line, not at the next line.
+    if !old.is_empty() && !old.ends_with('\n') {
+        let line = old_lines.len();
+        let col = old_lines.last().map_or(0, |last| last.chars().count()) + 1;
+        let appends = edits.last().map_or(false, |edit| edit.start_line > line);
+        if appends {
+            let edit = edits.last_mut().unwrap();
+            edit.new_text.insert(0, '\n');
+        } else if new.ends_with('\n') && edits.last().map_or(true, |edit| edit.end_line <= line) {
+            edits.push(TextEdit {
+                start_line: line + 1,
+                start_col: 1,
+                end_line: line + 1,
+                end_col: 1,
+                new_text: "\n".to_string(),
+            });
+        }
+        for edit in edits.iter_mut() {
+            if edit.start_line > line {
+                edit.start_line = line;
+                edit.start_col = col;
+            }
+            if edit.end_line > line {
+                edit.end_line = line;
+                edit.end_col = col;
+            }
+        }
+    }
+    edits
+}
+
+/// 1-based line of `old` where `old` and `new` begin to differ.
+pub fn first_diff_line(old: &str, new: &str) -> Option<usize> {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let pos = old_lines.iter().zip(&new_lines).position(|(a, b)| a != b);
+    match pos {
+        Some(pos) => Some(pos + 1),
+        None if old_lines.len() != new_lines.len() => Some(old_lines.len().min(new_lines.len()) + 1),
+        None => None,
+    }
+}
+
+/// Unified diff between `old` and `new`, empty if they have the same lines.
+pub fn unified_diff(old: &str, new: &str, path: &str, color: bool) -> String {
+    unified_diff_head(old, new, path, color, usize::max_value())
+}
+
+/// Like `unified_diff`, but only the first `max_hunks` hunks.
+pub fn unified_diff_head(old: &str, new: &str, path: &str, color: bool, max_hunks: usize) -> String {
+    let old_lines = lines(old);
+    let new_lines = lines(new);
+    let edits = diff_lines(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| !edits[i].is_equal()).collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut groups = Vec::new();
+    let mut first = changes[0];
+    let mut last = changes[0];
+    for &i in &changes[1..] {
+        if i - last > 2 * CONTEXT + 1 {
+            groups.push((first, last));
+            first = i;
+        }
+        last = i;
+    }
+    groups.push((first, last));
+
+    let (bold, cyan, green, red, reset) = if color {
+        (BOLD, CYAN, GREEN, RED, RESET)
+    } else {
+        ("", "", "", "", "")
+    };
+
+    let mut s = format!("{}--- a/{}\n+++ b/{}{}\n", bold, path, path, reset);
+    for (first, last) in groups.into_iter().take(max_hunks) {
+        let lo = first.saturating_sub(CONTEXT);
+        let hi = (last + CONTEXT + 1).min(edits.len());
+
+        let old_start = edits[..lo].iter().map(|e| e.old_len()).sum();
+        let new_start = edits[..lo].iter().map(|e| e.new_len()).sum();
+        let old_len = edits[lo..hi].iter().map(|e| e.old_len()).sum();
+        let new_len = edits[lo..hi].iter().map(|e| e.new_len()).sum();
+        s.push_str(&format!("{}@@ -{} +{} @@{}\n", cyan, hunk_range(old_start, old_len),
+                            hunk_range(new_start, new_len), reset));
+
+        for edit in &edits[lo..hi] {
+            let line = match *edit {
+                Edit::Equal(i, _) => format!(" {}", old_lines[i].trim_end_matches('\r')),
+                Edit::Delete(i) => format!("{}-{}{}", red, old_lines[i].trim_end_matches('\r'), reset),
+                Edit::Insert(i) => format!("{}+{}{}", green, new_lines[i].trim_end_matches('\r'), reset),
+            };
+            s.push_str(&line);
+            s.push('\n');
+        }
+    }
+    s
+}
+
+/// The 1-based inclusive lines of the new files which a unified diff adds or changes, by file, in order. The `b/`
+/// prefix of git is removed from the paths, and a deleted file has no line. A pure deletion counts as a change of the
+/// line before it.
+pub fn changed_lines(diff: &str) -> Vec<(String, Vec<(usize, usize)>)> {
+    let mut files: Vec<(String, Vec<(usize, usize)>)> = Vec::new();
+    let mut in_file = false;
+    // The old and new lines left in the current hunk, an added `++ a` line is not a `+++ b/a` header.
+    let mut hunk_left = (0, 0);
+    for line in diff.lines() {
+        if hunk_left != (0, 0) {
+            match line.as_bytes().first() {
+                Some(b'-') => hunk_left.0 = hunk_left.0.saturating_sub(1),
+                Some(b'+') => hunk_left.1 = hunk_left.1.saturating_sub(1),
+                Some(b'\\') => {},
+                _ => hunk_left = (hunk_left.0.saturating_sub(1), hunk_left.1.saturating_sub(1)),
+            }
+            continue;
+        }
+
+        if line.starts_with("+++ ") {
+            let path = line[4..].split('\t').next().unwrap_or_default().trim_end().trim_matches('"');
+            in_file = path != "/dev/null";
+            if in_file {
+                let path = if path.starts_with("b/") { &path[2..] } else { path };
+                files.push((path.to_string(), Vec::new()));
+            }
+        } else if line.starts_with("@@ ") {
+            if let Some(((_, old_len), (new_start, new_len))) = hunk_header(line) {
+                hunk_left = (old_len, new_len);
+                if let (true, Some(file)) = (in_file, files.last_mut()) {
+                    file.1.push(new_lines(new_start, new_len));
+                }
+            }
+        }
+    }
+    files
+}
+
+// The old and new `start,len` of a hunk header, `@@ -start,len +start,len @@`.
+fn hunk_header(header: &str) -> Option<((usize, usize), (usize, usize))> {
+    let mut ranges = header.split_whitespace().skip(1);
+    let old = ranges.next().filter(|range| range.starts_with('-'))?;
+    let new = ranges.next().filter(|range| range.starts_with('+'))?;
+    Some((parse_hunk_range(&old[1..])?, parse_hunk_range(&new[1..])?))
+}
+
+// The len is 1 if it is left out.
+#[inline]
+fn parse_hunk_range(range: &str) -> Option<(usize, usize)> {
+    let mut range = range.splitn(2, ',');
+    let start = range.next()?.parse::<usize>().ok()?;
+    let len = match range.next() {
+        Some(len) => len.parse::<usize>().ok()?,
+        None => 1,
+    };
+    Some((start, len))
+}
+
+// The start of an empty range is the line before it.
+#[inline]
+fn new_lines(start: usize, len: usize) -> (usize, usize) {
+    if len == 0 {
+        let line = cmp::max(start, 1);
+        (line, line)
+    } else {
+        (start, start + len - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(diff_lines(&[], &[]), vec![]);
+        assert_eq!(diff_lines(&[], &["a"]), vec![Edit::Insert(0)]);
+        assert_eq!(diff_lines(&["a"], &[]), vec![Edit::Delete(0)]);
+        assert_eq!(line_changes("", ""), vec![]);
+        assert_eq!(unified_diff("", "", "f.rs", false), "");
+        assert_eq!(changed_lines(""), vec![]);
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        assert_eq!(first_diff_line("a", "a\n"), None);
+        assert_eq!(unified_diff("a", "a\n", "f.rs", false), "");
+    }
+
+    #[test]
+    fn crlf() {
+        assert_eq!(unified_diff("a\r\nb\r\n", "a\r\nc\r\n", "f.rs", false),
+                   "--- a/f.rs\n+++ b/f.rs\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n");
+    }
+
+    #[test]
+    fn insertion() {
+        assert_eq!(diff_lines(&["a", "b", "c"], &["a", "c", "d"]),
+                   vec![Edit::Equal(0, 0), Edit::Delete(1), Edit::Equal(2, 1), Edit::Insert(2)]);
+        assert_eq!(line_changes("", "a\n"), vec![Change { start: 0, end: 0, text: "a\n".to_string() }]);
+        assert_eq!(line_changes("a\nb\n", "a\nx\nb\n"), vec![Change { start: 1, end: 1, text: "x\n".to_string() }]);
+
+        let diff = unified_diff("a\nb\n", "a\nx\nb\n", "f.rs", false);
+        assert_eq!(diff, "--- a/f.rs\n+++ b/f.rs\n@@ -1,2 +1,3 @@\n a\n+x\n b\n");
+        assert_eq!(changed_lines(&diff), vec![("f.rs".to_string(), vec![(1, 3)])]);
+    }
+
+    #[test]
+    fn deletion() {
+        assert_eq!(line_changes("a\n", ""), vec![Change { start: 0, end: 1, text: String::new() }]);
+        assert_eq!(line_changes("a\nx\nb\n", "a\nb\n"), vec![Change { start: 1, end: 2, text: String::new() }]);
+
+        let diff = unified_diff("a\nx\nb\n", "a\nb\n", "f.rs", false);
+        assert_eq!(diff, "--- a/f.rs\n+++ b/f.rs\n@@ -1,3 +1,2 @@\n a\n-x\n b\n");
+
+        let diff = unified_diff("a\n", "", "f.rs", false);
+        assert_eq!(diff, "--- a/f.rs\n+++ b/f.rs\n@@ -1,1 +0,0 @@\n-a\n");
+        assert_eq!(changed_lines(&diff), vec![("f.rs".to_string(), vec![(1, 1)])]);
+    }
+
+    #[test]
+    fn empty_new_range() {
+        let diff = "--- a/f.rs\n+++ b/f.rs\n@@ -3 +2,0 @@\n-x\n@@ -9,2 +7 @@\n-y\n z\n";
+        assert_eq!(changed_lines(diff), vec![("f.rs".to_string(), vec![(2, 2), (7, 7)])]);
+    }
+
+    #[test]
+    fn deleted_file() {
+        let diff = "--- a/f.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-x\n--- a/g.rs\n+++ b/g.rs\n@@ -1 +1 @@\n-x\n+y\n";
+        assert_eq!(changed_lines(diff), vec![("g.rs".to_string(), vec![(1, 1)])]);
+    }
+
+    #[test]
+    fn added_line_like_a_header() {
+        let diff = "--- a/f.rs\n+++ b/f.rs\n@@ -1,0 +2 @@\n+++ x\n@@ -4,0 +6 @@\n+++ b/y\n";
+        assert_eq!(changed_lines(diff), vec![("f.rs".to_string(), vec![(2, 2), (6, 6)])]);
+    }
+
+    // The byte offset of a 1-based line and char column.
+    fn offset(s: &str, line: usize, col: usize) -> usize {
+        let start: usize = s.split('\n').take(line - 1).map(|line| line.len() + 1).sum();
+        start + s[start..].chars().take(col - 1).map(char::len_utf8).sum::<usize>()
+    }
+
+    fn apply(old: &str, edits: &[TextEdit]) -> String {
+        let mut s = old.to_string();
+        for edit in edits.iter().rev() {
+            let start = offset(old, edit.start_line, edit.start_col);
+            let end = offset(old, edit.end_line, edit.end_col);
+            s.replace_range(start..end, &edit.new_text);
+        }
+        s
+    }
+
+    fn edit(start_line: usize, start_col: usize, end_line: usize, end_col: usize, new_text: &str) -> TextEdit {
+        TextEdit { start_line, start_col, end_line, end_col, new_text: new_text.to_string() }
+    }
+
+    #[test]
+    fn text_edits_reproduce_the_output() {
+        let cases = [
+            ("a", "a\n", vec![edit(1, 2, 1, 2, "\n")]),
+            ("a", "a\nb\n", vec![edit(1, 2, 1, 2, "\nb\n")]),
+            ("a\nb", "a\nc\n", vec![edit(2, 1, 2, 2, "c\n")]),
+            ("", "x\n", vec![edit(1, 1, 1, 1, "x\n")]),
+            ("a\nb\n", "a\nb\n", vec![]),
+            ("é\nb", "é\nb\nc\n", vec![edit(2, 2, 2, 2, "\nc\n")]),
+        ];
+        for (old, new, edits) in cases.iter() {
+            assert_eq!(&text_edits(old, new), edits, "{:?} -> {:?}", old, new);
+            assert_eq!(apply(old, edits), *new);
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use std::cmp;
+
+use syntax::print::pprust;
+
+use crate::ast;
+use crate::config::Config;
+use crate::ir::Pos;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delim {
+    Paren,
+    Bracket,
+    Brace,
+    None,
+}
+
+impl Delim {
+    #[inline]
+    fn open(self) -> &'static str {
+        match self {
+            Delim::Paren => "(",
+            Delim::Bracket => "[",
+            Delim::Brace => "{",
+            Delim::None => "",
+        }
+    }
+
+    #[inline]
+    fn close(self) -> &'static str {
+        match self {
+            Delim::Paren => ")",
+            Delim::Bracket => "]",
+            Delim::Brace => "}",
+            Delim::None => "",
+        }
+    }
+}
+
+/// A token tree of a parse, with the pos of its tokens but no span, so that the trees of two parses can be compared.
+#[derive(Clone, Debug)]
+pub enum TokenTree {
+    Token(Pos, String),
+    // The pos of the open delimiter, the delimiter, the trees and the pos of the close delimiter.
+    Group(Pos, Delim, Vec<TokenTree>, Pos),
+}
+
+impl TokenTree {
+    #[inline]
+    fn is_token(&self, s: &str) -> bool {
+        match *self {
+            TokenTree::Token(_, ref token) => token == s,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    fn is_group(&self, delim: Delim) -> bool {
+        match *self {
+            TokenTree::Group(_, group_delim, ..) => group_delim == delim,
+            _ => false,
+        }
+    }
+
+    #[inline]
+    fn pos(&self) -> Pos {
+        match *self {
+            TokenTree::Token(pos, _) | TokenTree::Group(pos, ..) => pos,
+        }
+    }
+}
+
+/// The trees of `stream`, it must be called with the globals of its parse.
+pub fn token_trees(stream: &ast::TokenStream) -> Vec<TokenTree> {
+    stream.trees().map(|tree| match tree {
+        ast::TokenTree::Token(token) => TokenTree::Token(token.span.lo().0, pprust::token_kind_to_string(&token.kind)),
+        ast::TokenTree::Delimited(span, delim, stream) => {
+            let delim = match delim {
+                ast::token::DelimToken::Paren => Delim::Paren,
+                ast::token::DelimToken::Bracket => Delim::Bracket,
+                ast::token::DelimToken::Brace => Delim::Brace,
+                ast::token::DelimToken::NoDelim => Delim::None,
+            };
+            TokenTree::Group(span.open.lo().0, delim, token_trees(&stream), span.close.lo().0)
+        },
+    }).collect()
+}
+
+/// Compares the tokens of two parses, so comments and white spaces are ignored. Before the comparison, what rfmt
+/// changes besides them is made the same in both parses:
+/// * In every mod, the `extern crate`, `use` and `mod` declarations are moved first, in this order, and each kind is
+///   sorted by its tokens if `reorder_imports` or `reorder_modules` is set.
+/// * The trees in every `{}` of a `use` are sorted if `reorder_imports` is set, and a `{}` of one tree is removed.
+/// * Every run of attributes which is not split by a doc comment is sorted, and the lists in an attribute too.
+/// * A trailing comma is removed, except the one of a one element `()`, which is a tuple. The comma after a `{}` is
+///   removed, it is optional after the block of a match arm.
+/// * `...` is `..=`, an `extern` without ABI is `extern "C"`, and the lines of a doc comment have no trailing white
+///   space.
+///
+/// Returns the position in `old_src` of the first divergent token, and what diverges.
+pub fn first_divergence(old_src: &str, old: Vec<TokenTree>, new_src: &str, new: Vec<TokenTree>, config: &Config)
+-> Option<(Pos, String)> {
+    let mut old_tokens = Vec::new();
+    flatten(canonical(old, List::Mod, Delim::None, config), &mut old_tokens);
+    let mut new_tokens = Vec::new();
+    flatten(canonical(new, List::Mod, Delim::None, config), &mut new_tokens);
+
+    for (idx, old_token) in old_tokens.iter().enumerate() {
+        let new_token = match new_tokens.get(idx) {
+            Some(new_token) => new_token,
+            None => {
+                let msg = format!("the output ends before `{}`", line_at(old_src, old_token.0));
+                return Some((old_token.0, msg));
+            },
+        };
+        if old_token.1 != new_token.1 {
+            let (old_line, new_line) = (line_at(old_src, old_token.0), line_at(new_src, new_token.0));
+            return Some((old_token.0, format!("expected `{}`, found `{}`", old_line, new_line)));
+        }
+    }
+
+    if let Some(new_token) = new_tokens.get(old_tokens.len()) {
+        let pos = old_tokens.last().map_or(0, |token| token.0);
+        return Some((pos, format!("the output has an extra `{}`", line_at(new_src, new_token.0))));
+    }
+    None
+}
+
+// What a list of trees is, it decides how the list is made canonical.
+#[derive(Clone, Copy, PartialEq)]
+enum List {
+    // The items of a mod, or of the crate.
+    Mod,
+    // The trees of an item which is a `use`.
+    Use,
+    // The trees in a `{}` of a `use`.
+    UseTrees,
+    // The trees in the `[]` of an attribute.
+    Attr,
+    // The trees in a `()` of an attribute.
+    AttrList,
+    Other,
+}
+
+// The trees of a list in `delim`, in the canonical form of `first_divergence`.
+fn canonical(trees: Vec<TokenTree>, list: List, delim: Delim, config: &Config) -> Vec<TokenTree> {
+    if list == List::Mod {
+        return canonical_mod(trees, config);
+    }
+
+    let mut canonical_trees: Vec<TokenTree> = Vec::with_capacity(trees.len());
+    for tree in trees {
+        let tree = match tree {
+            TokenTree::Group(open, delim, trees, close) => {
+                let inner = match list {
+                    _ if delim == Delim::Bracket && is_attr_start(&canonical_trees) => List::Attr,
+                    List::Attr | List::AttrList if delim == Delim::Paren => List::AttrList,
+                    List::Attr | List::AttrList => List::Other,
+                    List::Use | List::UseTrees if delim == Delim::Brace => List::UseTrees,
+                    _ if delim == Delim::Brace && is_mod_start(&canonical_trees) => List::Mod,
+                    _ => List::Other,
+                };
+                TokenTree::Group(open, delim, canonical(trees, inner, delim, config), close)
+            },
+            token => token,
+        };
+        canonical_trees.push(tree);
+    }
+
+    match list {
+        List::Use => unwrap_use_trees(sort_attrs(normalize(canonical_trees, delim, false))),
+        List::UseTrees => {
+            let elems = split_commas(canonical_trees).into_iter().map(unwrap_use_trees).collect();
+            join_commas(elems, config.reorder_imports)
+        },
+        List::AttrList => join_commas(split_commas(canonical_trees), true),
+        _ => sort_attrs(normalize(canonical_trees, delim, true)),
+    }
+}
+
+// The `extern crate`, `use` and `mod` declarations first, then the other items in order. The inner attributes and
+// doc comments of the mod stay before all of them.
+fn canonical_mod(mut trees: Vec<TokenTree>, config: &Config) -> Vec<TokenTree> {
+    let mut idx = 0;
+    loop {
+        match (trees.get(idx), trees.get(idx + 1), trees.get(idx + 2)) {
+            (Some(&TokenTree::Token(_, ref token)), ..) if token.starts_with("//!") || token.starts_with("/*!") => {
+                idx += 1;
+            },
+            (Some(pound), Some(not), Some(attr)) if pound.is_token("#") && not.is_token("!")
+                                                    && attr.is_group(Delim::Bracket) => idx += 3,
+            _ => break,
+        }
+    }
+    let items = trees.split_off(idx);
+    let mut canonical_trees = canonical(trees, List::Other, Delim::None, config);
+
+    let mut groups: [Vec<(String, Vec<TokenTree>)>; 3] = Default::default();
+    let mut others = Vec::new();
+    for item in split_items(items) {
+        let (idx, sorted) = match item_kind(&item) {
+            Some(idx) => (idx, if idx == 2 { config.reorder_modules } else { config.reorder_imports }),
+            None => {
+                others.extend(canonical(item, List::Other, Delim::None, config));
+                continue;
+            },
+        };
+        let item = canonical(item, if idx == 1 { List::Use } else { List::Other }, Delim::None, config);
+        let key = if sorted { tokens_string(&item) } else { String::new() };
+        groups[idx].push((key, item));
+    }
+
+    for group in groups.iter_mut() {
+        // A stable sort, the items are in order if they are not sorted.
+        group.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_, item) in group.drain(..) {
+            canonical_trees.extend(item);
+        }
+    }
+    canonical_trees.extend(others);
+    canonical_trees
+}
+
+// An item ends with a `;`, or with a `{}` which is not followed by a `;`.
+fn split_items(trees: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut trees = trees.into_iter().peekable();
+    while let Some(tree) = trees.next() {
+        let is_end = tree.is_token(";")
+                || tree.is_group(Delim::Brace) && !trees.peek().map_or(false, |tree| tree.is_token(";"));
+        item.push(tree);
+        if is_end {
+            items.push(item);
+            item = Vec::new();
+        }
+    }
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+// 0 for an `extern crate`, 1 for a `use` and 2 for a `mod` declaration, after the attributes, doc comments and
+// visibility.
+fn item_kind(item: &[TokenTree]) -> Option<usize> {
+    let mut idx = 0;
+    loop {
+        match (item.get(idx), item.get(idx + 1)) {
+            (Some(&TokenTree::Token(_, ref token)), _) if token.starts_with("///") || token.starts_with("/**") => {
+                idx += 1;
+            },
+            (Some(pound), Some(attr)) if pound.is_token("#") && attr.is_group(Delim::Bracket) => idx += 2,
+            _ => break,
+        }
+    }
+    if item.get(idx).map_or(false, |tree| tree.is_token("pub")) {
+        idx += 1;
+        if item.get(idx).map_or(false, |tree| tree.is_group(Delim::Paren)) {
+            idx += 1;
+        }
+    }
+
+    let is = |offset: usize, s: &str| item.get(idx + offset).map_or(false, |tree| tree.is_token(s));
+    if is(0, "extern") && is(1, "crate") {
+        Some(0)
+    } else if is(0, "use") {
+        Some(1)
+    } else if is(0, "mod") && is(2, ";") && item.len() == idx + 3 {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+// The trees before a `[]` are `#` or `#!`.
+#[inline]
+fn is_attr_start(trees: &[TokenTree]) -> bool {
+    let len = trees.len();
+    len >= 1 && trees[len - 1].is_token("#")
+            || len >= 2 && trees[len - 2].is_token("#") && trees[len - 1].is_token("!")
+}
+
+// The trees before a `{}` are `mod` and its name.
+#[inline]
+fn is_mod_start(trees: &[TokenTree]) -> bool {
+    let len = trees.len();
+    len >= 2 && trees[len - 2].is_token("mod") && match trees[len - 1] {
+        TokenTree::Token(..) => true,
+        _ => false,
+    }
+}
+
+// The token changes of rfmt, and the commas it adds or removes in a list in `delim`. A comma after a `{}` is removed
+// if `arm_commas`.
+fn normalize(trees: Vec<TokenTree>, delim: Delim, arm_commas: bool) -> Vec<TokenTree> {
+    let commas = trees.iter().filter(|tree| tree.is_token(",")).count();
+    let len = trees.len();
+    let mut normalized: Vec<TokenTree> = Vec::with_capacity(len);
+    let mut trees = trees.into_iter().enumerate().peekable();
+    while let Some((idx, tree)) = trees.next() {
+        let (pos, token) = match tree {
+            TokenTree::Token(pos, token) => (pos, token),
+            group => {
+                normalized.push(group);
+                continue;
+            },
+        };
+
+        if token == "," {
+            let is_last = idx + 1 == len && (delim != Delim::Paren || commas > 1);
+            let is_before_end = trees.peek().map_or(false, |&(_, ref next)| {
+                next.is_group(Delim::Brace) || next.is_token(">")
+            });
+            let is_after_block = arm_commas && normalized.last().map_or(false, |tree| tree.is_group(Delim::Brace));
+            if is_last || is_before_end || is_after_block {
+                continue;
+            }
+        }
+
+        let is_extern = token == "extern";
+        let token = if token == "..." {
+            "..=".to_string()
+        } else if is_doc(&token) {
+            token.lines().map(str::trim_end).collect::<Vec<_>>().join("\n")
+        } else {
+            token
+        };
+        normalized.push(TokenTree::Token(pos, token));
+
+        if is_extern {
+            let has_abi = trees.peek().map_or(false, |&(_, ref next)| match *next {
+                TokenTree::Token(_, ref next) => next == "crate" || next.starts_with('"') || next.starts_with('r'),
+                _ => false,
+            });
+            if !has_abi {
+                normalized.push(TokenTree::Token(pos, r#""C""#.to_string()));
+            }
+        }
+    }
+    normalized
+}
+
+#[inline]
+fn is_doc(token: &str) -> bool {
+    token.starts_with("///") || token.starts_with("//!") || token.starts_with("/**") || token.starts_with("/*!")
+}
+
+// Every run of attributes which are next to each other is sorted.
+fn sort_attrs(trees: Vec<TokenTree>) -> Vec<TokenTree> {
+    let mut sorted = Vec::with_capacity(trees.len());
+    let mut attrs: Vec<(String, Vec<TokenTree>)> = Vec::new();
+    let mut trees = trees.into_iter().peekable();
+    while let Some(tree) = trees.next() {
+        if tree.is_token("#") {
+            let mut attr = vec![tree];
+            if trees.peek().map_or(false, |tree| tree.is_token("!")) {
+                attr.push(trees.next().unwrap());
+            }
+            if trees.peek().map_or(false, |tree| tree.is_group(Delim::Bracket)) {
+                attr.push(trees.next().unwrap());
+                attrs.push((tokens_string(&attr), attr));
+                continue;
+            }
+            end_attrs(&mut attrs, &mut sorted);
+            sorted.extend(attr);
+            continue;
+        }
+
+        end_attrs(&mut attrs, &mut sorted);
+        sorted.push(tree);
+    }
+    end_attrs(&mut attrs, &mut sorted);
+    sorted
+}
+
+#[inline]
+fn end_attrs(attrs: &mut Vec<(String, Vec<TokenTree>)>, sorted: &mut Vec<TokenTree>) {
+    attrs.sort_by(|a, b| a.0.cmp(&b.0));
+    for (_, attr) in attrs.drain(..) {
+        sorted.extend(attr);
+    }
+}
+
+// `a::{b}` is `a::b`, a `{}` of one tree in a `use` is removed.
+fn unwrap_use_trees(trees: Vec<TokenTree>) -> Vec<TokenTree> {
+    let mut unwrapped = Vec::with_capacity(trees.len());
+    for tree in trees {
+        match tree {
+            TokenTree::Group(_, Delim::Brace, ref inner, _) if !inner.is_empty() && !inner.iter().any(|tree| {
+                tree.is_token(",")
+            }) => unwrapped.extend(inner.iter().cloned()),
+            tree => unwrapped.push(tree),
+        }
+    }
+    unwrapped
+}
+
+// The elements of a comma separated list, without the empty one after a trailing comma.
+fn split_commas(trees: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut elems = vec![Vec::new()];
+    for tree in trees {
+        if tree.is_token(",") {
+            elems.push(Vec::new());
+        } else {
+            elems.last_mut().unwrap().push(tree);
+        }
+    }
+    if elems.last().map_or(false, Vec::is_empty) {
+        elems.pop();
+    }
+    elems
+}
+
+fn join_commas(mut elems: Vec<Vec<TokenTree>>, sorted: bool) -> Vec<TokenTree> {
+    if sorted {
+        elems.sort_by_cached_key(|elem| tokens_string(elem));
+    }
+
+    let mut trees = Vec::new();
+    for elem in elems {
+        if let Some(pos) = elem.first().map(TokenTree::pos) {
+            if !trees.is_empty() {
+                trees.push(TokenTree::Token(pos, ",".to_string()));
+            }
+        }
+        trees.extend(elem);
+    }
+    trees
+}
+
+#[inline]
+fn tokens_string(trees: &[TokenTree]) -> String {
+    let mut tokens = Vec::new();
+    flatten(trees.to_vec(), &mut tokens);
+    tokens.into_iter().map(|(_, token)| token).collect::<Vec<_>>().join(" ")
+}
+
+fn flatten(trees: Vec<TokenTree>, tokens: &mut Vec<(Pos, String)>) {
+    for tree in trees {
+        match tree {
+            TokenTree::Token(pos, token) => tokens.push((pos, token)),
+            TokenTree::Group(open, delim, trees, close) => {
+                if delim != Delim::None {
+                    tokens.push((open, delim.open().to_string()));
+                }
+                flatten(trees, tokens);
+                if delim != Delim::None {
+                    tokens.push((close, delim.close().to_string()));
+                }
+            },
+        }
+    }
+}
+
+// The trimmed line of `pos` in `src`.
+#[inline]
+fn line_at(src: &str, pos: Pos) -> &str {
+    let pos = cmp::min(pos as usize, src.len());
+    let start = src[..pos].rfind('\n').map_or(0, |idx| idx + 1);
+    let end = src[pos..].find('\n').map_or(src.len(), |idx| pos + idx);
+    src[start..end].trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{verify_equivalent, Config, RfmtError};
+
+    fn verify(path: &str, src: &str, out: &str) -> Result<(), RfmtError> {
+        verify_equivalent(src, out, Path::new(path), &Config::default())
+    }
+
+    #[test]
+    fn sorted_output_is_equivalent() {
+        let src = include_str!("../tests/equiv/sorted.rs");
+        let out = include_str!("../tests/equiv/sorted.out.rs");
+        verify("sorted.rs", src, out).unwrap();
+    }
+
+    #[test]
+    fn changed_output_is_not_equivalent() {
+        let src = include_str!("../tests/equiv/diverged.rs");
+        let out = include_str!("../tests/equiv/diverged.out.rs");
+        match verify("diverged.rs", src, out) {
+            Err(RfmtError::NotEquivalent(loc, msg)) => {
+                assert_eq!((loc.line, loc.col), (4, 7));
+                assert_eq!(msg, "expected `a + b`, found `a - b`");
+            },
+            result => panic!("{:?}", result),
+        }
+    }
+}
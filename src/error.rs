@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use syntax::parse::ParseSess;
+
+use crate::ast;
+use crate::config::ConfigError;
+use crate::ir::Pos;
+
+#[derive(Clone, Debug, Default)]
+pub struct ErrorLoc {
+    pub path: PathBuf,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl ErrorLoc {
+    pub fn file(path: &Path) -> ErrorLoc {
+        ErrorLoc {
+            path: path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_span(sess: &ParseSess, sp: ast::Span) -> ErrorLoc {
+        let pos = sess.source_map().lookup_char_pos(sp.lo());
+        ErrorLoc {
+            path: PathBuf::from(pos.file.name.to_string()),
+            line: pos.line,
+            col: pos.col.0 + 1,
+        }
+    }
+
+    pub fn from_pos(path: &Path, src: &str, pos: Pos) -> ErrorLoc {
+        let before = src.get(..pos as usize).unwrap_or(src);
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        ErrorLoc {
+            path: path.to_path_buf(),
+            line: before.matches('\n').count() + 1,
+            col: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for ErrorLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.path.display())
+        } else {
+            write!(f, "{}:{}:{}", self.path.display(), self.line, self.col)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RfmtError {
+    Io(ErrorLoc, io::Error),
+    Config(ConfigError),
+    Parse(ErrorLoc, String),
+    Unsupported(ErrorLoc, String),
+    Macro(ErrorLoc, String),
+    NotEquivalent(ErrorLoc, String),
+    CommentLoss(ErrorLoc, Vec<(u32, String)>),
+    Panic(ErrorLoc, String),
+}
+
+impl RfmtError {
+    pub fn io(path: &Path, e: io::Error) -> RfmtError {
+        RfmtError::Io(ErrorLoc::file(path), e)
+    }
+}
+
+impl Display for RfmtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RfmtError::Io(ref loc, ref e) => write!(f, "{}\n  --> {}", e, loc),
+            RfmtError::Config(ref e) => {
+                let loc = ErrorLoc {
+                    path: e.path.clone(),
+                    line: e.line,
+                    col: e.col,
+                };
+                write!(f, "{}\n  --> {}", e.msg, loc)
+            },
+            RfmtError::Parse(ref loc, ref msg) => write!(f, "{}\n  --> {}", msg, loc),
+            RfmtError::Unsupported(ref loc, ref msg) => write!(f, "unsupported syntax: {}\n  --> {}", msg, loc),
+            RfmtError::Macro(ref loc, ref msg) => write!(f, "can not format macro: {}\n  --> {}", msg, loc),
+            RfmtError::CommentLoss(ref loc, ref cmnts) => {
+                write!(f, "{} comment(s) would be lost", cmnts.len())?;
+                for &(line, ref cmnt) in cmnts {
+                    write!(f, "\n  --> {}:{}: {}", loc.path.display(), line, cmnt)?;
+                }
+                Ok(())
+            },
+            RfmtError::NotEquivalent(ref loc, ref msg) => {
+                write!(f, "formatted output is not equivalent to the source: {}\n  --> {}", msg, loc)
+            },
+            RfmtError::Panic(ref loc, ref msg) => write!(f, "rfmt panicked: {}\n  --> {}", msg, loc),
+        }
+    }
+}
+
+impl Error for RfmtError {}
+
+impl From<ConfigError> for RfmtError {
+    fn from(e: ConfigError) -> RfmtError {
+        RfmtError::Config(e)
+    }
+}
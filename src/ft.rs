@@ -1,10 +1,13 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{self, Display};
+use std::mem;
 
 use ir::*;
 use ts::*;
 
+use crate::config::Config;
 use crate::ir;
+use crate::source_map::Mapping;
 use crate::ts;
 use crate::{need_nl_indent, need_wrap};
 
@@ -189,6 +192,7 @@ impl Display for Item {
             ItemKind::Impl(ref item) => Display::fmt(item, f)?,
             ItemKind::MacroDef(ref item) => Display::fmt(item, f)?,
             ItemKind::Macro(ref item) => Display::fmt(item, f)?,
+            ItemKind::Skip(ref item) => Display::fmt(item, f)?,
         }
         OK
     }
@@ -618,6 +622,7 @@ impl Display for TraitItem {
             TraitItemKind::Type(ref item) => Display::fmt(item, f),
             TraitItemKind::Method(ref item) => Display::fmt(item, f),
             TraitItemKind::Macro(ref item) => Display::fmt(item, f),
+            TraitItemKind::Skip(ref item) => Display::fmt(item, f),
         }
     }
 }
@@ -701,6 +706,7 @@ impl Display for ImplItem {
                 Display::fmt(item, f)?
             },
             ImplItemKind::Macro(ref item) => Display::fmt(item, f)?,
+            ImplItemKind::Skip(ref item) => return Display::fmt(item, f),
         }
         if !is_method {
             write!(f, ";")?;
@@ -833,6 +839,7 @@ impl Display for Stmt {
             StmtKind::Let(ref item) => Display::fmt(item, f),
             StmtKind::Expr(ref item, is_semi) => display_expr(f, item, is_semi),
             StmtKind::Macro(ref item) => Display::fmt(item, f),
+            StmtKind::Skip(ref item) => Display::fmt(item, f),
         }
     }
 }
@@ -883,6 +890,7 @@ impl Display for Expr {
             ExprKind::Closure(ref expr) => Display::fmt(expr, f),
             ExprKind::Return(ref expr) => Display::fmt(expr, f),
             ExprKind::Macro(ref expr) => Display::fmt(expr, f),
+            ExprKind::Skip(ref expr) => Display::fmt(expr, f),
         }
     }
 }
@@ -1069,6 +1077,10 @@ impl Display for MatchExpr {
 
 impl Display for Arm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.pattens.is_empty() {
+            return Display::fmt(&self.body, f);
+        }
+
         display_pattens(f, &self.pattens)?;
         if let Some(ref guard) = self.guard {
             write!(f, " if {}", guard)?;
@@ -1460,6 +1472,19 @@ fn exract_if_else_value(expr: &IfExpr) -> (&Expr, &Expr) {
     (if_value, else_value)
 }
 
+#[inline]
+fn is_inline_comment(cmnt: &str) -> bool {
+    cmnt.starts_with("/*") && cmnt.ends_with("*/")
+}
+
+#[inline]
+fn is_block_indent_open(open: &str) -> bool {
+    match open {
+        "(" | "[" | "<" => true,
+        _ => false,
+    }
+}
+
 macro_rules! can_one_line {
     ($sf:expr, $e:expr) => ({
         $sf.ts.can_one_line(&$e.to_string())
@@ -1529,6 +1554,54 @@ macro_rules! insert_sep {
 }
 
 macro_rules! fmt_comma_lists {
+    ($sf:expr, $open:expr, $close:expr, $($list:expr, $fmt:ident),+) => ({
+        let count = 0 $(+ $list.len())+;
+        // The length is only needed, and rendered, for block indent.
+        let block_indent = $sf.config.block_indent && is_block_indent_open($open) && count > 0 && {
+            let len = $open.len() + $close.len() + 2 * count
+                      $(+ $list.iter().map(|e| e.to_string().len()).sum::<usize>())+;
+            !$sf.ts.can_one_line_len(len)
+        };
+        if block_indent {
+            fmt_block_indent_lists!($sf, $open, $close, count, $($list, $fmt),+)
+        } else {
+            fmt_visual_lists!($sf, $open, $close, $($list, $fmt),+)
+        }
+    });
+
+    ($sf:expr, $($list:expr, $fmt:ident),+) => ({
+        fmt_comma_lists!($sf, "", "", $($list, $fmt)+);
+    });
+}
+
+macro_rules! fmt_block_indent_lists {
+    ($sf:expr, $open:expr, $close:expr, $count:expr, $($list:expr, $fmt:ident),+) => ({
+        $sf.raw_insert($open);
+        $sf.indent();
+
+        let mut first = true;
+        $(for e in $list {
+            if !first {
+                $sf.raw_insert(",");
+            }
+
+            $sf.nl_indent();
+            $sf.$fmt(e);
+            first = false;
+        })+
+
+        // A trailing comma makes a one element parenthesized expression, type or patten a tuple.
+        if $sf.config.trailing_comma && $count > 1 {
+            $sf.raw_insert(",");
+        }
+        $sf.outdent();
+        $sf.nl_indent();
+        $sf.raw_insert($close);
+        true
+    });
+}
+
+macro_rules! fmt_visual_lists {
     ($sf:expr, $open:expr, $close:expr, $($list:expr, $fmt:ident),+) => ({
         let mut is_wrap = false;
         $sf.insert_mark_align($open);
@@ -1546,9 +1619,77 @@ macro_rules! fmt_comma_lists {
         $sf.insert_unmark_align($close);
         is_wrap
     });
+}
+
+// Like `fmt_comma_lists`, but also formats the comments of the elements.
+// A line comment makes the list vertical, as nothing can follow it in the same line.
+macro_rules! fmt_comment_lists {
+    ($sf:expr, $open:expr, $close:expr, $($list:expr, $fmt:ident),+) => ({
+        if false $(|| $list.iter().any(|e| $sf.has_line_comments(&e.loc)))+ {
+            fmt_vertical_comment_lists!($sf, $open, $close, $($list, $fmt),+)
+        } else if false $(|| $list.iter().any(|e| $sf.has_list_comments(&e.loc)))+ {
+            fmt_visual_comment_lists!($sf, $open, $close, $($list, $fmt),+)
+        } else {
+            fmt_comma_lists!($sf, $open, $close, $($list, $fmt),+)
+        }
+    });
 
     ($sf:expr, $($list:expr, $fmt:ident),+) => ({
-        fmt_comma_lists!($sf, "", "", $($list, $fmt)+);
+        fmt_comment_lists!($sf, "", "", $($list, $fmt)+);
+    });
+}
+
+macro_rules! fmt_vertical_comment_lists {
+    ($sf:expr, $open:expr, $close:expr, $($list:expr, $fmt:ident),+) => ({
+        $sf.raw_insert($open);
+        $sf.indent();
+
+        let mut pre_loc: Option<Loc> = None;
+        $(for e in $list {
+            if let Some(ref loc) = pre_loc {
+                $sf.raw_insert(",");
+                $sf.try_fmt_trailing_comment(loc);
+            }
+
+            $sf.nl_indent();
+            $sf.fmt_list_leading_comments(&e.loc);
+            $sf.$fmt(e);
+            pre_loc = Some(e.loc);
+        })+
+
+        if let Some(ref loc) = pre_loc {
+            // The same as `fmt_block_indent_lists`, a one element list keeps its shape.
+            if $sf.config.trailing_comma && 0 $(+ $list.len())+ > 1 {
+                $sf.raw_insert(",");
+            }
+            $sf.try_fmt_trailing_comment(loc);
+        }
+        $sf.outdent();
+        $sf.nl_indent();
+        $sf.raw_insert($close);
+        true
+    });
+}
+
+macro_rules! fmt_visual_comment_lists {
+    ($sf:expr, $open:expr, $close:expr, $($list:expr, $fmt:ident),+) => ({
+        let mut is_wrap = false;
+        $sf.insert_mark_align($open);
+
+        let mut first = true;
+        $(for e in $list {
+            if !first {
+                is_wrap |= insert_sep!($sf, ",", e);
+            }
+
+            $sf.fmt_list_leading_comments(&e.loc);
+            $sf.$fmt(e);
+            $sf.try_fmt_trailing_comment(&e.loc);
+            first = false;
+        })+
+
+        $sf.insert_unmark_align($close);
+        is_wrap
     });
 }
 
@@ -1591,14 +1732,14 @@ macro_rules! fmt_use_trees {
 }
 
 macro_rules! fmt_item_groups {
-    ($sf:expr, $items:expr, $item_kind:path, $item_type:ty, $fmt_item:ident) => ({
+    ($sf:expr, $items:expr, $item_kind:path, $item_type:ty, $fmt_item:ident, $sort:expr) => ({
         let mut group: Vec<(&Loc, &String, &Vec<AttrKind>, $item_type)> = Vec::new();
 
         for item in $items {
             match item.item {
                 $item_kind(ref e) => {
                     if $sf.has_leading_comments(&item.loc) {
-                        fmt_item_group!($sf, &group, $item_type, $fmt_item);
+                        fmt_item_group!($sf, &group, $item_type, $fmt_item, $sort);
                         group.clear();
 
                         $sf.fmt_leading_comments(&item.loc);
@@ -1606,27 +1747,34 @@ macro_rules! fmt_item_groups {
                     group.push((&item.loc, &item.vis, &item.attrs, e));
                 }
                 _ => {
-                    fmt_item_group!($sf, &group, $item_type, $fmt_item);
+                    fmt_item_group!($sf, &group, $item_type, $fmt_item, $sort);
                     group.clear();
                 }
             }
         }
 
-        fmt_item_group!($sf, &group, $item_type, $fmt_item);
+        fmt_item_group!($sf, &group, $item_type, $fmt_item, $sort);
     });
 }
 
 macro_rules! fmt_item_group {
-    ($sf:expr, $group:expr, $ty:ty, $fmt_item:ident) => ({
-        let map: BTreeMap<String, (&Loc, &String, &Vec<AttrKind>, $ty)>
-                = $group.into_iter().map(|e| (e.3.to_string(), *e)).collect();
+    ($sf:expr, $group:expr, $ty:ty, $fmt_item:ident, $sort:expr) => ({
+        let group: Vec<(&Loc, &String, &Vec<AttrKind>, $ty)> = if $sort {
+            let map: BTreeMap<String, (&Loc, &String, &Vec<AttrKind>, $ty)>
+                    = $group.into_iter().map(|e| (e.3.to_string(), *e)).collect();
+            map.into_iter().map(|(_, e)| e).collect()
+        } else {
+            $group.to_vec()
+        };
 
-        for (_, e) in map {
+        for e in group {
             $sf.fmt_attrs(e.2);
 
             $sf.insert_indent();
+            $sf.ts.begin_node(e.0);
             $sf.fmt_vis(e.1);
             $sf.$fmt_item(e.3);
+            $sf.ts.end_node();
 
             $sf.try_fmt_trailing_comment(e.0);
             $sf.nl();
@@ -1674,11 +1822,18 @@ macro_rules! fmt_block {
     ($sf:expr, $items: expr, $block:expr, $fmt:ident) => ({
         if $items.is_empty() {
             if $sf.block_non_sep {
-                $sf.raw_insert("{}");
+                $sf.raw_insert("{");
                 $sf.block_non_sep = false;
             } else {
-                $sf.raw_insert(" {}");
+                $sf.raw_insert(" {");
             }
+            // `{ // TODO` keeps its comment, and the `}` goes to the next line.
+            let end = $sf.block_locs.last().unwrap().end;
+            if $sf.try_fmt_brace_comment(end) {
+                $sf.nl();
+                $sf.insert_indent();
+            }
+            $sf.raw_insert("}");
             return;
         }
 
@@ -1688,6 +1843,7 @@ macro_rules! fmt_block {
         } else {
             $sf.raw_insert(" {");
         }
+        $sf.try_fmt_brace_comment($items[0].loc.start);
 
         let loc = Loc {
             start: $sf.block_locs.last().unwrap().end,
@@ -1730,28 +1886,62 @@ macro_rules! fmt_items_maybe_nl {
             if !$sf.try_fmt_leading_comments(&item.loc) && nl {
                 $sf.nl();
             }
+            let start = $sf.ts.offset();
 
             $sf.fmt_attrs(&item.attrs);
             $sf.insert_indent();
+            $sf.ts.begin_node(&item.loc);
             nl = $sf.$fmt_item(item);
+            $sf.ts.end_node();
 
             $sf.try_fmt_trailing_comment(&item.loc);
             $sf.nl();
+            $sf.add_node_span(&item.loc, start);
         }
     });
 }
 
-pub fn fmt(krate: Crate, leading_cmnts: HashMap<Pos, Vec<String>>, trailing_cmnts: HashMap<Pos, String>) -> TsResult {
-    Formatter::new(leading_cmnts, trailing_cmnts).fmt_crate(krate)
+/// The items of a mod split after every skipped item, which includes an `rfmt-off` region. A skipped item is kept in
+/// place, so the extern crates, uses and mod decls are only grouped and sorted with the items of their segment.
+pub fn mod_segments(items: &[Item]) -> Vec<&[Item]> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for (idx, item) in items.iter().enumerate() {
+        if let ItemKind::Skip(_) = item.item {
+            segments.push(&items[start..=idx]);
+            start = idx + 1;
+        }
+    }
+    segments.push(&items[start..]);
+    segments
+}
+
+pub struct FtResult {
+    pub s: String,
+    pub exceed_lines: BTreeSet<u32>,
+    pub trailing_ws_lines: BTreeSet<u32>,
+    pub lost_cmnts: Vec<Comment>,
+    // The output of every item, impl item, trait item and statement, from the start of its first line to the end of
+    // its last line, without its leading comments. Keyed by the start and end of its loc.
+    pub node_spans: HashMap<(Pos, Pos), (usize, usize)>,
+    pub mappings: Vec<Mapping>,
+}
+
+pub fn fmt(krate: Crate, leading_cmnts: HashMap<Pos, Vec<String>>, trailing_cmnts: HashMap<Pos, String>,
+           brace_cmnts: BTreeMap<Pos, String>, config: &Config) -> FtResult {
+    Formatter::new(leading_cmnts, trailing_cmnts, brace_cmnts, config).fmt_crate(krate)
 }
 
 struct Formatter {
+    config: Config,
     ts: Typesetter,
 
     leading_cmnts: HashMap<Pos, Vec<String>>,
     trailing_cmnts: HashMap<Pos, String>,
+    brace_cmnts: BTreeMap<Pos, String>,
     block_locs: Vec<Loc>,
     if_stacks: u8,
+    node_spans: HashMap<(Pos, Pos), (usize, usize)>,
 
     after_indent: bool,
     after_wrap: bool,
@@ -1759,14 +1949,18 @@ struct Formatter {
 }
 
 impl Formatter {
-    fn new(leading_cmnts: HashMap<Pos, Vec<String>>, trailing_cmnts: HashMap<Pos, String>) -> Formatter {
+    fn new(leading_cmnts: HashMap<Pos, Vec<String>>, trailing_cmnts: HashMap<Pos, String>,
+           brace_cmnts: BTreeMap<Pos, String>, config: &Config) -> Formatter {
         Formatter {
-            ts: Typesetter::new(),
+            config: config.clone(),
+            ts: Typesetter::new(config),
 
             leading_cmnts,
             trailing_cmnts,
+            brace_cmnts,
             block_locs: Vec::new(),
             if_stacks: 0,
+            node_spans: HashMap::new(),
 
             after_indent: false,
             after_wrap: false,
@@ -1774,12 +1968,48 @@ impl Formatter {
         }
     }
 
-    fn fmt_crate(mut self, krate: Crate) -> TsResult {
+    fn fmt_crate(mut self, krate: Crate) -> FtResult {
         self.try_fmt_leading_comments(&krate.loc);
         self.fmt_attrs(&krate.attrs);
         self.fmt_mod(&krate.module);
         self.fmt_left_comments(&krate.module.loc);
-        self.ts.result()
+
+        let lost_cmnts = self.lost_comments();
+        let ts_result = self.ts.result();
+        FtResult {
+            s: ts_result.s,
+            exceed_lines: ts_result.exceed_lines,
+            trailing_ws_lines: ts_result.trailing_ws_lines,
+            lost_cmnts,
+            node_spans: self.node_spans,
+            mappings: ts_result.mappings,
+        }
+    }
+
+    #[inline]
+    fn add_node_span(&mut self, loc: &Loc, start: usize) {
+        self.node_spans.insert((loc.start, loc.end), (start, self.ts.offset()));
+    }
+
+    // Comments that are never formatted, they are not in the output.
+    fn lost_comments(&mut self) -> Vec<Comment> {
+        let mut cmnts: Vec<Comment> = self.leading_cmnts.drain().map(|(pos, lines)| {
+            Comment {
+                pos,
+                kind: CommentKind::Leading,
+                lines: lines.into_iter().filter(|line| !line.is_empty()).collect(),
+            }
+        }).filter(|cmnt| !cmnt.lines.is_empty()).collect();
+        let trailing_cmnts = self.trailing_cmnts.drain().chain(mem::replace(&mut self.brace_cmnts, BTreeMap::new()));
+        cmnts.extend(trailing_cmnts.map(|(pos, line)| {
+            Comment {
+                pos,
+                kind: CommentKind::Trailing,
+                lines: vec![line],
+            }
+        }));
+        cmnts.sort_by_key(|cmnt| cmnt.pos);
+        cmnts
     }
 
     #[inline]
@@ -1827,15 +2057,56 @@ impl Formatter {
         self.raw_insert(&cmnt);
     }
 
+    #[inline]
+    fn has_list_comments(&self, loc: &Loc) -> bool {
+        self.has_leading_comments(loc) || self.has_trailing_comment(loc)
+    }
+
+    fn has_line_comments(&self, loc: &Loc) -> bool {
+        let leading = self.leading_cmnts.get(&loc.start).map_or(false, |cmnts| {
+            cmnts.iter().any(|cmnt| !cmnt.is_empty() && !is_inline_comment(cmnt))
+        });
+        let trailing = self.trailing_cmnts.get(&loc.end).map_or(false, |cmnt| !is_inline_comment(cmnt));
+        leading || trailing
+    }
+
+    // Leading comments of a list element, an inline comment is kept in the same line with the element.
+    fn fmt_list_leading_comments(&mut self, loc: &Loc) {
+        if let Some(cmnts) = self.leading_cmnts.remove(&loc.start) {
+            for cmnt in cmnts.iter().filter(|cmnt| !cmnt.is_empty()) {
+                self.raw_insert(cmnt);
+                if is_inline_comment(cmnt) {
+                    self.raw_insert(" ");
+                } else {
+                    self.nl_indent();
+                }
+            }
+        }
+    }
+
+    // The comment after a `{`, which is after the start of the node of the block and before its first element.
+    #[inline]
+    fn try_fmt_brace_comment(&mut self, end: Pos) -> bool {
+        let start = self.block_locs.last().map_or(0, |loc| loc.start);
+        let pos = self.brace_cmnts.range(start..end).next().map(|(&pos, _)| pos);
+        if let Some(pos) = pos {
+            let cmnt = self.brace_cmnts.remove(&pos).unwrap();
+            self.raw_insert(" ");
+            self.raw_insert(&cmnt);
+            true
+        } else {
+            false
+        }
+    }
+
     #[inline]
     fn fmt_left_comments(&mut self, loc: &Loc) {
-        let poses: Vec<_> = self.leading_cmnts.keys().cloned().collect();
+        let mut poses: Vec<_> = self.leading_cmnts.keys().cloned().filter(|&pos| pos > loc.end).collect();
+        poses.sort();
         for pos in poses {
             for cmnt in &self.leading_cmnts.remove(&pos).unwrap() {
-                if pos > loc.end {
-                    self.raw_insert(cmnt);
-                    self.nl();
-                }
+                self.raw_insert(cmnt);
+                self.nl();
             }
         }
     }
@@ -1894,6 +2165,18 @@ impl Formatter {
         }
     }
 
+    fn fmt_skip(&mut self, skip: &Skip) {
+        let mut first = true;
+        for line in skip.s.split('\n') {
+            if !first {
+                self.nl();
+            }
+
+            self.raw_insert(line.trim_end_matches('\r'));
+            first = false;
+        }
+    }
+
     fn fmt_attr_group(&mut self, attr_group: &Vec<&Attr>) {
         let sorted_attrs: BTreeMap<_, _> = attr_group.into_iter().map(|e| (e.to_string(), *e)).collect();
         for attr in sorted_attrs.values() {
@@ -1930,26 +2213,29 @@ impl Formatter {
     }
 
     fn fmt_mod(&mut self, module: &Mod) {
-        self.fmt_group_items(&module.items);
-        self.fmt_items(&module.items);
+        for items in mod_segments(&module.items) {
+            self.fmt_group_items(items);
+            self.fmt_items(items);
+        }
     }
 
-    fn fmt_group_items(&mut self, items: &Vec<Item>) {
+    fn fmt_group_items(&mut self, items: &[Item]) {
         self.fmt_extern_crate_items(items);
         self.fmt_use_items(items);
         self.fmt_mod_decl_items(items);
     }
 
-    fn fmt_extern_crate_items(&mut self, items: &Vec<Item>) {
-        fmt_item_groups!(self, items, ItemKind::ExternCrate, &ExternCrate, fmt_extern_crate);
+    fn fmt_extern_crate_items(&mut self, items: &[Item]) {
+        fmt_item_groups!(self, items, ItemKind::ExternCrate, &ExternCrate, fmt_extern_crate,
+                         self.config.reorder_imports);
     }
 
     fn fmt_extern_crate(&mut self, item: &ExternCrate) {
         self.insert(&format!("extern crate {};", &item.name));
     }
 
-    fn fmt_use_items(&mut self, items: &Vec<Item>) {
-        fmt_item_groups!(self, items, ItemKind::Use, &Use, fmt_use);
+    fn fmt_use_items(&mut self, items: &[Item]) {
+        fmt_item_groups!(self, items, ItemKind::Use, &Use, fmt_use, self.config.reorder_imports);
     }
 
     #[inline]
@@ -1978,15 +2264,15 @@ impl Formatter {
         }
     }
 
-    fn fmt_mod_decl_items(&mut self, items: &Vec<Item>) {
-        fmt_item_groups!(self, items, ItemKind::ModDecl, &ModDecl, fmt_mod_decl);
+    fn fmt_mod_decl_items(&mut self, items: &[Item]) {
+        fmt_item_groups!(self, items, ItemKind::ModDecl, &ModDecl, fmt_mod_decl, self.config.reorder_modules);
     }
 
     fn fmt_mod_decl(&mut self, item: &ModDecl) {
         self.insert(&format!("mod {};", &item.name));
     }
 
-    fn fmt_items(&mut self, items: &Vec<Item>) {
+    fn fmt_items(&mut self, items: &[Item]) {
         let mut nl = false;
         for item in items {
             nl = match item.item {
@@ -2000,8 +2286,10 @@ impl Formatter {
         if !self.try_fmt_leading_comments(&item.loc) && nl {
             self.nl();
         }
+        let start = self.ts.offset();
         self.fmt_attrs(&item.attrs);
         self.insert_indent();
+        self.ts.begin_node(&item.loc);
         self.fmt_vis(&item.vis);
 
         self.block_locs.push(item.loc);
@@ -2066,11 +2354,17 @@ impl Formatter {
                 self.fmt_macro_item(item);
                 false
             },
+            ItemKind::Skip(ref item) => {
+                self.fmt_skip(item);
+                false
+            },
         };
         self.block_locs.pop();
+        self.ts.end_node();
 
         self.try_fmt_trailing_comment(&item.loc);
         self.nl();
+        self.add_node_span(&item.loc, start);
         nl
     }
 
@@ -2088,8 +2382,8 @@ impl Formatter {
 
     fn fmt_generics(&mut self, generics: &Generics) {
         if !generics.is_empty() {
-            fmt_comma_lists!(self, "<", ">", &generics.lifetime_defs, fmt_lifetime_def,
-                             &generics.type_params, fmt_type_param);
+            fmt_comment_lists!(self, "<", ">", &generics.lifetime_defs, fmt_lifetime_def,
+                               &generics.type_params, fmt_type_param);
         }
     }
 
@@ -2163,14 +2457,39 @@ impl Formatter {
     #[inline]
     fn fmt_where(&mut self, generics: &Generics) {
         let wh = &generics.wh;
-        if !wh.is_empty() {
+        if wh.is_empty() {
+            return;
+        }
+
+        if wh.clauses.iter().any(|e| self.has_line_comments(&e.loc)) {
+            self.fmt_vertical_where_clauses(&wh.clauses);
+        } else {
             maybe_nl_indent!(self, " where ", "where ", wh);
             self.fmt_where_clauses(&wh.clauses);
         }
     }
 
     fn fmt_where_clauses(&mut self, clauses: &Vec<WhereClause>) {
-        fmt_comma_lists!(self, clauses, fmt_where_clause);
+        fmt_comment_lists!(self, clauses, fmt_where_clause);
+    }
+
+    // The `{` or `;` after the where clauses starts a new line, so the last clause keeps its trailing comment too.
+    fn fmt_vertical_where_clauses(&mut self, clauses: &Vec<WhereClause>) {
+        self.nl_indent();
+        self.raw_insert("where");
+        self.indent();
+
+        let last = clauses.len() - 1;
+        for (idx, clause) in clauses.iter().enumerate() {
+            self.nl_indent();
+            self.fmt_list_leading_comments(&clause.loc);
+            self.fmt_where_clause(clause);
+            if idx != last || self.config.trailing_comma {
+                self.raw_insert(",");
+            }
+            self.try_fmt_trailing_comment(&clause.loc);
+        }
+        self.outdent();
     }
 
     #[inline]
@@ -2275,6 +2594,7 @@ impl Formatter {
 
     fn fmt_type(&mut self, ty: &Type) {
         maybe_nl!(self, ty);
+        self.ts.begin_node(&ty.loc);
         match ty.ty {
             TypeKind::Symbol(ref ty) => self.fmt_symbol_type(ty),
             TypeKind::Path(ref ty) => self.fmt_path_type(ty, false),
@@ -2287,6 +2607,7 @@ impl Formatter {
             TypeKind::BareFn(ref ty) => self.fmt_bare_fn_type(ty),
             TypeKind::Macro(ref ty) => self.fmt_macro(ty),
         }
+        self.ts.end_node();
     }
 
     #[inline]
@@ -2538,6 +2859,10 @@ impl Formatter {
                 self.fmt_macro(item);
                 false
             },
+            TraitItemKind::Skip(ref item) => {
+                self.fmt_skip(item);
+                false
+            },
         }
     }
 
@@ -2612,6 +2937,10 @@ impl Formatter {
                 self.fmt_method_impl_item(item);
             },
             ImplItemKind::Macro(ref item) => self.fmt_macro(item),
+            ImplItemKind::Skip(ref item) => {
+                self.fmt_skip(item);
+                return false;
+            },
         }
         if !is_method {
             self.raw_insert(";");
@@ -2657,7 +2986,7 @@ impl Formatter {
 
     #[inline]
     fn fmt_fn_args(&mut self, args: &Vec<Arg>) -> bool {
-        fmt_comma_lists!(self, "(", ")", args, fmt_arg)
+        fmt_comment_lists!(self, "(", ")", args, fmt_arg)
     }
 
     #[inline]
@@ -2718,8 +3047,10 @@ impl Formatter {
 
     fn fmt_block(&mut self, block: &Block) {
         self.block_locs.push(block.loc);
+        self.ts.begin_node(&block.loc);
         self.insert(&block_head(block.is_unsafe));
         fmt_block!(self, &block.stmts, fmt_stmts);
+        self.ts.end_node();
         self.block_locs.pop();
     }
 
@@ -2733,6 +3064,8 @@ impl Formatter {
     fn fmt_stmt(&mut self, stmt: &Stmt) {
         self.block_locs.push(stmt.loc);
         self.try_fmt_leading_comments(&stmt.loc);
+        let start = self.ts.offset();
+        self.ts.begin_node(&stmt.loc);
         match stmt.stmt {
             StmtKind::Item(ref item) => {
                 self.fmt_item(item, false);
@@ -2740,10 +3073,21 @@ impl Formatter {
             StmtKind::Let(ref local) => self.fmt_let(local),
             StmtKind::Expr(ref expr, is_semi) => self.fmt_expr_stmt(expr, is_semi),
             StmtKind::Macro(ref mac) => self.fmt_macro_stmt(mac),
+            StmtKind::Skip(ref skip) => self.fmt_skip_stmt(&stmt.loc, skip),
         }
+        self.ts.end_node();
+        self.add_node_span(&stmt.loc, start);
         self.block_locs.pop();
     }
 
+    #[inline]
+    fn fmt_skip_stmt(&mut self, loc: &Loc, skip: &Skip) {
+        self.insert_indent();
+        self.fmt_skip(skip);
+        self.try_fmt_trailing_comment(loc);
+        self.nl();
+    }
+
     fn fmt_let(&mut self, local: &Let) {
         self.try_fmt_leading_comments(&local.loc);
         self.fmt_attrs(&local.attrs);
@@ -2765,6 +3109,7 @@ impl Formatter {
 
     fn fmt_patten(&mut self, patten: &Patten) {
         maybe_nl!(self, patten);
+        self.ts.begin_node(&patten.loc);
         match patten.patten {
             PattenKind::Wildcard => self.insert("_"),
             PattenKind::Symbol(ref patten) => self.insert(patten),
@@ -2779,6 +3124,7 @@ impl Formatter {
             PattenKind::Slice(ref patten) => self.fmt_slice_patten(patten),
             PattenKind::Macro(ref patten) => self.fmt_macro(patten),
         }
+        self.ts.end_node();
     }
 
     fn fmt_pattens(&mut self, pattens: &Vec<Patten>) {
@@ -2824,7 +3170,7 @@ impl Formatter {
             return;
         }
 
-        self.open_brace();
+        self.open_brace(patten.fields[0].loc.start);
         let loc = Loc {
             start: self.block_locs.last().unwrap().end,
             ..Default::default()
@@ -2910,6 +3256,7 @@ impl Formatter {
     fn fmt_expr(&mut self, expr: &Expr) {
         self.block_locs.push(expr.loc);
         maybe_nl!(self, expr);
+        self.ts.begin_node(&expr.loc);
         match expr.expr {
             ExprKind::Literal(ref expr) => self.fmt_literal_expr(expr),
             ExprKind::Path(ref expr) => self.fmt_path_expr(expr),
@@ -2940,7 +3287,9 @@ impl Formatter {
             ExprKind::Closure(ref expr) => self.fmt_closure_expr(expr),
             ExprKind::Return(ref expr) => self.fmt_return_expr(expr),
             ExprKind::Macro(ref expr) => self.fmt_macro(expr),
+            ExprKind::Skip(ref expr) => self.fmt_skip(expr),
         }
+        self.ts.end_node();
         self.block_locs.pop();
     }
 
@@ -3012,7 +3361,7 @@ impl Formatter {
             return;
         }
 
-        self.open_brace();
+        self.open_brace(expr.fields[0].loc.start);
         let loc = Loc {
             start: self.block_locs.last().unwrap().end,
             ..Default::default()
@@ -3210,6 +3559,11 @@ impl Formatter {
 
     #[inline]
     fn fmt_arm(&mut self, arm: &Arm) {
+        if let ExprKind::Skip(ref skip) = arm.body.expr {
+            self.fmt_skip(skip);
+            return;
+        }
+
         fmt_lists!(self, " | ", "| ", &arm.pattens, fmt_patten);
         if let Some(ref guard) = arm.guard {
             maybe_wrap!(self, " if ", "if ", guard, fmt_expr);
@@ -3289,7 +3643,7 @@ impl Formatter {
     #[inline]
     fn fmt_macro_def(&mut self, item: &MacroDef) {
         self.raw_insert(&format!("macro_rules! {}", item.name));
-        self.open_brace();
+        self.open_brace(item.def_pos);
         self.insert_indent();
         self.force_insert(&item.def);
         self.nl();
@@ -3432,8 +3786,9 @@ impl Formatter {
     }
 
     #[inline]
-    fn open_brace(&mut self) {
+    fn open_brace(&mut self, first_pos: Pos) {
         self.raw_insert(" {");
+        self.try_fmt_brace_comment(first_pos);
         self.indent();
         self.nl();
     }
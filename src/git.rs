@@ -0,0 +1,35 @@
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use rfmt::RfmtError;
+
+const GIT: &str = "git";
+
+// The unchanged lines are left out, and the paths are relative to the current dir with the usual `a/` and `b/`
+// prefixes, whatever the git config says.
+const DIFF_ARGS: [&str; 7] = ["diff", "-U0", "--no-color", "--no-ext-diff", "--relative", "--src-prefix=a/",
+                              "--dst-prefix=b/"];
+
+// The diff of the work tree against the index, or of the index against HEAD if `staged`.
+pub fn diff(staged: bool) -> Result<String, RfmtError> {
+    let mut args = DIFF_ARGS.to_vec();
+    if staged {
+        args.push("--cached");
+    }
+    run(&args, Path::new("."))
+}
+
+// The content of `path` in the index, `path` is relative to the current dir.
+pub fn staged(path: &Path) -> Result<String, RfmtError> {
+    run(&["show", &format!(":./{}", path.display())], path)
+}
+
+fn run(args: &[&str], path: &Path) -> Result<String, RfmtError> {
+    let output = Command::new(GIT).args(args).output().map_err(|e| RfmtError::io(path, e))?;
+    if !output.status.success() {
+        let msg = format!("`{} {}` failed: {}", GIT, args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+        return Err(RfmtError::io(path, io::Error::new(io::ErrorKind::Other, msg)));
+    }
+    String::from_utf8(output.stdout).map_err(|e| RfmtError::io(path, io::Error::new(io::ErrorKind::InvalidData, e)))
+}
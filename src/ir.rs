@@ -62,6 +62,8 @@ pub struct Crate {
 
 pub type Doc = Chunk;
 
+pub type Skip = Chunk;
+
 #[derive(Debug)]
 pub enum AttrKind {
     Doc(Doc),
@@ -119,6 +121,7 @@ pub enum ItemKind {
     Impl(Impl),
     MacroDef(MacroDef),
     Macro(Macro),
+    Skip(Skip),
 }
 
 #[derive(Debug)]
@@ -604,6 +607,7 @@ pub enum TraitItemKind {
     Type(TypeTraitItem),
     Method(MethodTraitItem),
     Macro(Macro),
+    Skip(Skip),
 }
 
 #[derive(Debug)]
@@ -654,6 +658,7 @@ pub enum ImplItemKind {
     Existential(ExistentialImplItem),
     Method(MethodImplItem),
     Macro(Macro),
+    Skip(Skip),
 }
 
 pub type ConstImplItem = Const;
@@ -716,6 +721,7 @@ pub enum StmtKind {
     Let(Let),
     Expr(Expr, bool),
     Macro(MacroStmt),
+    Skip(Skip),
 }
 
 #[derive(Debug)]
@@ -841,6 +847,7 @@ pub enum ExprKind {
     Closure(Box<ClosureExpr>),
     Return(Box<ReturnExpr>),
     Macro(Macro),
+    Skip(Skip),
 }
 
 pub type PathExpr = PathType;
@@ -1009,6 +1016,8 @@ pub struct ReturnExpr {
 #[derive(Debug)]
 pub struct MacroDef {
     pub name: String,
+    // The start of `def`, a comment right after the `{` of the macro is before it.
+    pub def_pos: Pos,
     pub def: String,
 }
 
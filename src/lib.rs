@@ -0,0 +1,277 @@
+use std::cmp;
+use std::collections::BTreeSet;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use syntax::parse::{self, PResult, ParseSess, lexer::comments};
+use syntax::source_map::FilePathMapping;
+use syntax_pos::FileName;
+
+use crate::ast;
+use crate::ir::{Comment, CommentKind};
+
+pub use crate::config::{Config, NewlineStyle, Style};
+pub use crate::diff::TextEdit;
+pub use crate::source_map::SourceMap;
+pub use crate::error::{ErrorLoc, RfmtError};
+
+mod ast;
+pub mod config;
+pub mod diff;
+mod equiv;
+mod error;
+mod ft;
+mod ir;
+mod modules;
+mod partial;
+pub mod source_map;
+mod tr;
+mod ts;
+
+const STR_PATH: &str = "<str>";
+
+#[derive(Debug)]
+pub struct Formatted {
+    pub s: String,
+    pub exceed_lines: BTreeSet<u32>,
+    pub trailing_ws_lines: BTreeSet<u32>,
+    pub warnings: Vec<String>,
+    /// Line and text of the comments that are in the source but not in the output.
+    pub lost_comments: Vec<(u32, String)>,
+    /// Maps the offsets of the source to the output.
+    pub source_map: SourceMap,
+}
+
+impl Formatted {
+    /// Whether there are exceed lines, trailing white space lines, warnings or lost comments.
+    pub fn has_problems(&self) -> bool {
+        !self.exceed_lines.is_empty() || !self.trailing_ws_lines.is_empty() || !self.warnings.is_empty()
+                || !self.lost_comments.is_empty()
+    }
+}
+
+pub fn format_str(src: &str, config: &Config) -> Result<Formatted, RfmtError> {
+    format(src.to_string(), Path::new(STR_PATH), config)
+}
+
+/// Like `format_str`, errors are reported against `path`.
+pub fn format_str_with_path(src: &str, path: &Path, config: &Config) -> Result<Formatted, RfmtError> {
+    format(src.to_string(), path, config)
+}
+
+/// Like `format_str_with_path`, but only the items, impl items, trait items and statements which overlap one of the
+/// byte `ranges` are formatted, the rest of `src` is kept as is. The innermost of them is formatted, unless it shares
+/// a line with another node or has no output of its own, then its parent is. `extern crate`, `use` and `mod`
+/// declarations are not formatted this way. If no node can be formatted alone, `src` is kept with a warning.
+pub fn format_ranges(src: &str, path: &Path, config: &Config, ranges: &[Range<usize>])
+-> Result<Formatted, RfmtError> {
+    let tr_result = trans(src.to_string(), path, config)?;
+    match partial::select(src, &tr_result.krate, ranges) {
+        Some(selected) => Ok(format_trans(src, tr_result, config, Some(selected))),
+        None => Ok(unchanged(src, tr_result.warnings)),
+    }
+}
+
+/// Like `format_str_with_path`, but returns the edits which turn `src` into its formatted output, see
+/// `diff::text_edits`. Like `--overwrite`, it fails if the output loses comments or is not equivalent to `src`.
+pub fn format_edits(src: &str, path: &Path, config: &Config) -> Result<Vec<TextEdit>, RfmtError> {
+    let formatted = format(src.to_string(), path, config)?;
+    if !formatted.lost_comments.is_empty() {
+        return Err(RfmtError::CommentLoss(ErrorLoc::file(path), formatted.lost_comments));
+    }
+    verify_equivalent(src, &formatted.s, path, config)?;
+    Ok(diff::text_edits(src, &formatted.s))
+}
+
+pub fn format_file(path: &Path, config: &Config) -> Result<Formatted, RfmtError> {
+    let src = fs::read_to_string(path).map_err(|e| RfmtError::io(path, e))?;
+    format(src, path, config)
+}
+
+/// Returns true if `src` is already formatted and has no problems.
+pub fn check_str(src: &str, config: &Config) -> Result<bool, RfmtError> {
+    let formatted = format_str(src, config)?;
+    Ok(formatted.s == src && !formatted.has_problems())
+}
+
+/// Re-parses `formatted` and checks that it has the same tokens as `src`,
+/// ignoring comments and the order of what rfmt sorts, see `equiv::first_divergence`.
+pub fn verify_equivalent(src: &str, formatted: &str, path: &Path, config: &Config) -> Result<(), RfmtError> {
+    let old = token_trees(src, path)?;
+    let new = match token_trees(formatted, path) {
+        Ok(new) => new,
+        Err(RfmtError::Parse(loc, msg)) => {
+            let msg = format!("the output can not be parsed at line {}: {}", loc.line, msg);
+            return Err(RfmtError::NotEquivalent(ErrorLoc::file(path), msg));
+        },
+        Err(e) => return Err(e),
+    };
+
+    match equiv::first_divergence(src, old, formatted, new, config) {
+        Some((pos, msg)) => Err(RfmtError::NotEquivalent(ErrorLoc::from_pos(path, src, pos), msg)),
+        None => Ok(()),
+    }
+}
+
+/// The files of the module trees of the crate `roots`, following the out of line `mod` declarations and their
+/// `#[path]`. A module whose file is not found is an error in place of the file.
+pub fn crate_files(roots: &[PathBuf]) -> Vec<Result<PathBuf, RfmtError>> {
+    modules::crate_files(roots)
+}
+
+#[doc(hidden)]
+pub fn debug_ir(src: String, path: &Path, config: &Config) -> Result<(String, String), RfmtError> {
+    let result = trans(src, path, config)?;
+    let cmnts = format!("{:#?}\n{:#?}\n{:#?}", result.leading_cmnts, result.trailing_cmnts, result.brace_cmnts);
+    Ok((format!("{:#?}", result.krate), cmnts))
+}
+
+#[doc(hidden)]
+pub fn print_ir(src: String, path: &Path, config: &Config) -> Result<String, RfmtError> {
+    let result = trans(src, path, config)?;
+    Ok(result.krate.to_string())
+}
+
+fn format(src: String, path: &Path, config: &Config) -> Result<Formatted, RfmtError> {
+    let tr_result = trans(src.clone(), path, config)?;
+    Ok(format_trans(&src, tr_result, config, None))
+}
+
+// Formats the whole crate, and keeps only the output of the `selected` nodes if specified.
+fn format_trans(src: &str, tr_result: tr::TrResult, config: &Config, selected: Option<Vec<partial::Selected>>)
+-> Formatted {
+    let ft_result = ft::fmt(tr_result.krate, tr_result.leading_cmnts, tr_result.trailing_cmnts, tr_result.brace_cmnts,
+                            config);
+    let mut lost = lost_comments(src, ft_result.lost_cmnts);
+
+    if let Some(selected) = selected {
+        let spliced = match partial::splice(src, &selected, &ft_result, config.newline_style) {
+            Some(spliced) => spliced,
+            None => return unchanged(src, tr_result.warnings),
+        };
+        // The other comments are kept with their lines.
+        lost.retain(|&(line, _)| spliced.lines.iter().any(|lines| {
+            line_of(src, lines.start) <= line && line < line_of(src, lines.end)
+        }));
+        return Formatted {
+            lost_comments: lost,
+            source_map: SourceMap::new(spliced.mappings, spliced.s.len()),
+            s: spliced.s,
+            exceed_lines: spliced.exceed_lines,
+            trailing_ws_lines: spliced.trailing_ws_lines,
+            warnings: tr_result.warnings,
+        };
+    }
+
+    let s = config.newline_style.apply(src, ft_result.s.clone());
+    let crlf = s.len() != ft_result.s.len();
+    let mappings = source_map::move_mappings(&ft_result.mappings, &ft_result.s, 0..ft_result.s.len(), 0, crlf);
+    Formatted {
+        lost_comments: lost,
+        source_map: SourceMap::new(mappings, s.len()),
+        s,
+        exceed_lines: ft_result.exceed_lines,
+        trailing_ws_lines: ft_result.trailing_ws_lines,
+        warnings: tr_result.warnings,
+    }
+}
+
+// The ranges can not be formatted without the whole crate, which is not what is asked, so `src` is kept as is.
+fn unchanged(src: &str, mut warnings: Vec<String>) -> Formatted {
+    warnings.push("the ranges can not be formatted alone, they are kept as is".to_string());
+    let mapping = source_map::Mapping {
+        src: 0..src.len(),
+        out: 0..src.len(),
+    };
+    Formatted {
+        s: src.to_string(),
+        exceed_lines: BTreeSet::new(),
+        trailing_ws_lines: BTreeSet::new(),
+        warnings,
+        lost_comments: Vec::new(),
+        source_map: SourceMap::new(vec![mapping], src.len()),
+    }
+}
+
+// The comments only know the pos of the node they belong to, so search their lines in the source.
+fn lost_comments(src: &str, cmnts: Vec<Comment>) -> Vec<(u32, String)> {
+    let mut lost = Vec::new();
+    for cmnt in cmnts {
+        let pos = cmp::min(cmnt.pos as usize, src.len());
+        match cmnt.kind {
+            CommentKind::Leading => {
+                let mut end = pos;
+                let mut lines = Vec::new();
+                for line in cmnt.lines.iter().rev() {
+                    let line = line.trim();
+                    if let Some(idx) = src[..end].rfind(line) {
+                        end = idx;
+                    }
+                    lines.push((line_of(src, end), line.to_string()));
+                }
+                lost.extend(lines.into_iter().rev());
+            },
+            CommentKind::Trailing => {
+                let line = cmnt.lines[0].trim();
+                let idx = src[pos..].find(line).map_or(pos, |idx| pos + idx);
+                lost.push((line_of(src, idx), line.to_string()));
+            },
+        }
+    }
+    lost
+}
+
+#[inline]
+fn line_of(src: &str, pos: usize) -> u32 {
+    src[..pos].matches('\n').count() as u32 + 1
+}
+
+// Every module file is parsed on its own, the out of line modules are not loaded, so a foo.rs which declares modules
+// in foo/ is parsed like the others.
+fn parse_crate<'a>(sess: &'a ParseSess, path: &Path, src: String) -> PResult<'a, ast::Crate> {
+    let mut parser = parse::new_parser_from_source_str(sess, FileName::from(path.to_path_buf()), src);
+    parser.recurse_into_file_modules = false;
+    parser.parse_crate_mod()
+}
+
+fn parse_src(sess: &ParseSess, path: &Path, src: String) -> Result<ast::Crate, RfmtError> {
+    let krate = match parse_crate(sess, path, src) {
+        Ok(krate) => krate,
+        Err(mut e) => {
+            let loc = match e.span.primary_span() {
+                Some(sp) => ErrorLoc::from_span(sess, sp),
+                None => ErrorLoc::file(path),
+            };
+            let err = RfmtError::Parse(loc, e.message());
+            e.cancel();
+            return Err(err);
+        },
+    };
+    if sess.span_diagnostic.has_errors() {
+        return Err(RfmtError::Parse(ErrorLoc::file(path), "could not parse the source".to_string()));
+    }
+    Ok(krate)
+}
+
+// The source must be a crate, but it is compared by its tokens.
+fn token_trees(src: &str, path: &Path) -> Result<Vec<equiv::TokenTree>, RfmtError> {
+    syntax::with_default_globals(|| {
+        parse_src(&ParseSess::new(FilePathMapping::empty()), path, src.to_string())?;
+        // A new session, the pos of its only file start at 0 like the ones of the crate.
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let stream = parse::parse_stream_from_source_str(FileName::from(path.to_path_buf()), src.to_string(), &sess,
+                                                         None);
+        Ok(equiv::token_trees(&stream))
+    })
+}
+
+fn trans(src: String, path: &Path, config: &Config) -> Result<tr::TrResult, RfmtError> {
+    syntax::with_default_globals(|| {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = parse_src(&sess, path, src.clone())?;
+
+        let cmnts = comments::gather_comments(&sess, FileName::from(path.to_path_buf()), src.clone());
+        tr::trans(src, sess, krate, cmnts, config)
+    })
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use rfmt::config;
+use rfmt::diff;
+use rfmt::{ErrorLoc, RfmtError, Style, TextEdit};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const STDIN_PATH: &str = "<stdin>";
+const FILE_URI_PREFIX: &str = "file://";
+
+// JSON-RPC and LSP error codes.
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+// The documents are sent whole on every change.
+const TEXT_DOCUMENT_SYNC_FULL: u64 = 1;
+
+type ResponseError = (i64, String);
+
+struct Server {
+    style: Option<Style>,
+    docs: HashMap<String, String>,
+    shutdown: bool,
+}
+
+// Lines `start..=end`, the nodes which overlap them are formatted.
+#[derive(Clone, Copy)]
+struct LineRange {
+    start: usize,
+    end: usize,
+}
+
+impl LineRange {
+    // From the start of the first line to the start of the line after the last one.
+    #[inline]
+    fn bytes(self, text: &str) -> Range<usize> {
+        offset(text, self.start, 0)..offset(text, self.end + 1, 0)
+    }
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, ResponseError> {
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "the server is shut down".to_string()));
+        }
+
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": TEXT_DOCUMENT_SYNC_FULL,
+                    "documentFormattingProvider": true,
+                    "documentRangeFormattingProvider": true,
+                    "documentOnTypeFormattingProvider": {
+                        "firstTriggerCharacter": "}",
+                        "moreTriggerCharacter": [";"]
+                    }
+                },
+                "serverInfo": {
+                    "name": "rfmt",
+                    "version": VERSION
+                }
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            },
+            "textDocument/formatting" => self.format(params, None),
+            "textDocument/rangeFormatting" => {
+                let range = LineRange {
+                    start: param_usize(params, "/range/start/line")?,
+                    end: range_end_line(params)?,
+                };
+                self.format(params, Some(range))
+            },
+            "textDocument/onTypeFormatting" => {
+                let uri = param_str(params, "/textDocument/uri")?;
+                let line = param_usize(params, "/position/line")?;
+                let character = param_usize(params, "/position/character")?;
+                let ch = param_str(params, "/ch")?;
+                let range = match self.docs.get(uri) {
+                    Some(text) => on_type_range(text, line, character, ch),
+                    None => return Ok(Value::Null),
+                };
+                // The code is often incomplete while typing, nothing is better than an error popup.
+                Ok(self.format(params, Some(range)).unwrap_or(Value::Null))
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) {
+        match method {
+            "textDocument/didOpen" => {
+                if let (Ok(uri), Ok(text)) = (param_str(params, "/textDocument/uri"),
+                                              param_str(params, "/textDocument/text")) {
+                    self.docs.insert(uri.to_string(), text.to_string());
+                }
+            },
+            "textDocument/didChange" => {
+                let text = params.pointer("/contentChanges").and_then(Value::as_array)
+                                 .and_then(|changes| changes.last())
+                                 .and_then(|change| change["text"].as_str());
+                if let (Ok(uri), Some(text)) = (param_str(params, "/textDocument/uri"), text) {
+                    self.docs.insert(uri.to_string(), text.to_string());
+                }
+            },
+            "textDocument/didClose" => {
+                if let Ok(uri) = param_str(params, "/textDocument/uri") {
+                    self.docs.remove(uri);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // The edits which format the document, only the nodes overlapping `range` if specified.
+    fn format(&self, params: &Value, range: Option<LineRange>) -> Result<Value, ResponseError> {
+        let uri = param_str(params, "/textDocument/uri")?;
+        let src = match self.docs.get(uri) {
+            Some(src) => src,
+            None => return Err((INVALID_PARAMS, format!("document not open: {}", uri))),
+        };
+
+        let path = uri_to_path(uri);
+        let range = range.map(|range| range.bytes(src));
+        let formatted = format_src(src, &path, self.style, range).map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+        let lines: Vec<_> = src.split('\n').collect();
+        let edits = diff::text_edits(src, &formatted).iter().map(|edit| text_edit(&lines, edit)).collect();
+        Ok(Value::Array(edits))
+    }
+}
+
+// Like `--overwrite`, the formatting is refused if it loses comments or is not equivalent to the source.
+fn format_src(src: &str, path: &Path, style: Option<Style>, range: Option<Range<usize>>) -> Result<String, RfmtError> {
+    let config = if path.is_absolute() {
+        config::load(path, style)?
+    } else {
+        config::load_from_cwd(style)?
+    };
+    let result = match range {
+        Some(range) => rfmt::format_ranges(src, path, &config, &[range])?,
+        None => rfmt::format_str_with_path(src, path, &config)?,
+    };
+    if !result.lost_comments.is_empty() {
+        return Err(RfmtError::CommentLoss(ErrorLoc::file(path), result.lost_comments));
+    }
+    rfmt::verify_equivalent(src, &result.s, path, &config)?;
+    Ok(result.s)
+}
+
+// Serves LSP over stdin and stdout until the `exit` notification. Returns the exit code, which is 1 if the client did
+// not ask to shut down first.
+pub fn run(style: Option<Style>) -> Result<i32, RfmtError> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    let io_error = |e| RfmtError::io(Path::new(STDIN_PATH), e);
+
+    let mut server = Server {
+        style,
+        docs: HashMap::new(),
+        shutdown: false,
+    };
+    while let Some(msg) = read_message(&mut input).map_err(io_error)? {
+        let method = match msg["method"].as_str() {
+            Some(method) => method,
+            // A response to a request of the server, rfmt does not send any.
+            None => continue,
+        };
+        if method == "exit" {
+            return Ok(if server.shutdown { 0 } else { 1 });
+        }
+
+        match msg.get("id") {
+            Some(id) => {
+                let response = match server.request(method, &msg["params"]) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    Err((code, message)) => {
+                        json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+                    },
+                };
+                write_message(&mut output, &response).map_err(io_error)?;
+            },
+            None => server.notify(method, &msg["params"]),
+        }
+    }
+    Ok(if server.shutdown { 0 } else { 1 })
+}
+
+// A message is a `Content-Length` header, other headers, an empty line and the JSON content.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header"))?;
+    let mut content = vec![0; len];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message<W: Write>(output: &mut W, msg: &Value) -> io::Result<()> {
+    let content = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    output.flush()
+}
+
+// The same edit as `--emit=edits-json`, with the 0-based positions of LSP.
+#[inline]
+fn text_edit(lines: &[&str], edit: &TextEdit) -> Value {
+    json!({
+        "range": {
+            "start": position(lines, edit.start_line, edit.start_col),
+            "end": position(lines, edit.end_line, edit.end_col)
+        },
+        "newText": edit.new_text
+    })
+}
+
+// The LSP position of a 1-based line and char column, whose character counts UTF-16 code units.
+#[inline]
+fn position(lines: &[&str], line: usize, col: usize) -> Value {
+    let character = lines.get(line - 1).map_or(0, |text| {
+        text.chars().take(col - 1).map(char::len_utf16).sum::<usize>()
+    });
+    json!({"line": line - 1, "character": character})
+}
+
+#[inline]
+fn param_str<'a>(params: &'a Value, pointer: &str) -> Result<&'a str, ResponseError> {
+    params.pointer(pointer).and_then(Value::as_str).ok_or_else(|| missing_param(pointer))
+}
+
+#[inline]
+fn param_usize(params: &Value, pointer: &str) -> Result<usize, ResponseError> {
+    params.pointer(pointer).and_then(Value::as_u64).map(|n| n as usize).ok_or_else(|| missing_param(pointer))
+}
+
+#[inline]
+fn missing_param(pointer: &str) -> ResponseError {
+    (INVALID_PARAMS, format!("missing or invalid param: {}", &pointer[1..]))
+}
+
+// A range which ends at the start of a line does not include this line.
+fn range_end_line(params: &Value) -> Result<usize, ResponseError> {
+    let start = param_usize(params, "/range/start/line")?;
+    let end = param_usize(params, "/range/end/line")?;
+    let character = param_usize(params, "/range/end/character")?;
+    if end > start && character == 0 {
+        Ok(end - 1)
+    } else {
+        Ok(end)
+    }
+}
+
+// The lines of what was just finished: the block closed by a `}`, or the line of a `;`.
+fn on_type_range(text: &str, line: usize, character: usize, ch: &str) -> LineRange {
+    let mut range = LineRange {
+        start: line,
+        end: line,
+    };
+    if ch == "}" {
+        let end = offset(text, line, character);
+        if let Some(open) = open_brace(&text[..end]) {
+            range.start = text[..open].matches('\n').count();
+        }
+    }
+    range
+}
+
+// The byte offset of an LSP position, whose character counts UTF-16 code units.
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let line_start = text.split('\n').take(line).map(|line| line.len() + 1).sum::<usize>();
+    let line_start = line_start.min(text.len());
+    let mut units = 0;
+    for (idx, ch) in text[line_start..].char_indices() {
+        if units >= character || ch == '\n' {
+            return line_start + idx;
+        }
+        units += ch.len_utf16();
+    }
+    text.len()
+}
+
+// The `{` which matches the last `}` of `text`. Braces in strings and comments are counted too, it is only used to
+// find which lines to format.
+fn open_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, ch) in text.char_indices().rev() {
+        match ch {
+            '}' => depth += 1,
+            '{' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    if !uri.starts_with(FILE_URI_PREFIX) {
+        return PathBuf::from(uri);
+    }
+
+    let path = percent_decode(&uri[FILE_URI_PREFIX.len()..]);
+    // file:///C:/foo.rs on Windows.
+    let bytes = path.as_bytes();
+    if bytes.len() > 2 && bytes[0] == b'/' && bytes[2] == b':' && bytes[1].is_ascii_alphabetic() {
+        PathBuf::from(&path[1..])
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = s.get(idx + 1..idx + 3).filter(|_| bytes[idx] == b'%')
+                                          .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                idx += 3;
+            },
+            None => {
+                decoded.push(bytes[idx]);
+                idx += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
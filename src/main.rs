@@ -1,13 +1,17 @@
 use std::path::PathBuf;
+use std::process;
 
 use structopt::StructOpt;
 
-mod ast;
-mod ft;
-mod ir;
-mod rfmt;
-mod tr;
-mod ts;
+use rfmt::Style;
+
+use crate::cli::{ByteRange, Color, Emit, FileLines};
+
+mod cache;
+mod cli;
+mod git;
+mod lsp;
+mod walk;
 
 #[derive(Debug, StructOpt)]
 pub struct Opt {
@@ -15,14 +19,41 @@ pub struct Opt {
     /// Print the rust original syntax ast debug info
     ast: bool,
 
-    #[structopt(long, short)]
-    /// Check exceed lines and trailing white space lines
+    #[structopt(long, short, conflicts_with = "overwrite")]
+    /// Check files that are not formatted, exceed lines and trailing white space lines
     check: bool,
 
+    #[structopt(long = "crate")]
+    /// Format the module tree of the crate root `input`, following its `mod` declarations. If `input` is a dir, the
+    /// crate roots are found like cargo does, src/lib.rs, src/main.rs, src/bin, tests, examples, benches and build.rs
+    krate: bool,
+
     #[structopt(long, short)]
     /// Print the rfmt ir debug info
     debug: bool,
 
+    #[structopt(long, number_of_values = 1, parse(try_from_str = "walk::parse_exclude"))]
+    /// Skip the paths matching this gitignore style glob when walking a dir, can be repeated
+    exclude: Vec<String>,
+
+    #[structopt(long = "diff-filter", conflicts_with = "lines", conflicts_with = "range")]
+    /// Format only the items and statements touched by the diff of `git diff`, or by a unified diff from stdin if
+    /// `input` is `-`. Otherwise only the files in `input` are formatted
+    diff_filter: bool,
+
+    #[structopt(long, short, default_value = "1")]
+    /// Format the files of a dir on this many threads, the output is still in path order
+    jobs: usize,
+
+    #[structopt(long, number_of_values = 1)]
+    /// Format only the items and statements in these lines of a file, `file:start-end`, can be repeated. The other
+    /// files are not formatted
+    lines: Vec<FileLines>,
+
+    #[structopt(long = "list-different", short = "l", conflicts_with = "overwrite")]
+    /// Check like `--check`, but only print the paths of the files that fail
+    list_different: bool,
+
     #[structopt(long, short)]
     /// Print the rfmt ir simple format
     print: bool,
@@ -31,24 +62,141 @@ pub struct Opt {
     /// Overwrite the source file
     overwrite: bool,
 
+    #[structopt(long = "allow-comment-loss")]
+    /// Overwrite the source file or emit its edits even if some of its comments would be lost
+    allow_comment_loss: bool,
+
+    #[structopt(long)]
+    /// Print a unified diff of what formatting would change, same as `--emit=diff`
+    diff: bool,
+
+    #[structopt(long)]
+    /// Where to emit the formatted source: stdout, files, diff, edits-json
+    emit: Option<Emit>,
+
+    #[structopt(long = "no-cache")]
+    /// Neither read nor update the cache of formatted files, even if `cache` is enabled in the config
+    no_cache: bool,
+
+    #[structopt(long, short)]
+    /// Print the paths excluded when walking a dir, and by which rule
+    verbose: bool,
+
+    #[structopt(long = "verify-idempotent")]
+    /// Format every file twice and report the files where the second pass changes the output
+    verify_idempotent: bool,
+
+    #[structopt(long, default_value = "auto")]
+    /// Color the diff: auto, always, never. `auto` colors only when stdout is a terminal
+    color: Color,
+
+    #[structopt(long)]
+    /// Print to stderr the offset in the output of this byte offset of the input, to keep the cursor of an editor in
+    /// place
+    cursor: Option<usize>,
+
+    #[structopt(long = "print-config")]
+    /// Print the effective config for `input`, or for the current dir if `input` is not specified
+    print_config: bool,
+
+    #[structopt(long)]
+    /// Format only the items and statements in this byte range of the input, `start..end`
+    range: Option<ByteRange>,
+
+    #[structopt(long, requires = "diff_filter")]
+    /// With `--diff-filter`, format the staged content of the files with the diff of `git diff --cached`, for a
+    /// pre-commit hook. `--overwrite` refuses the files with unstaged changes
+    staged: bool,
+
+    #[structopt(long)]
+    /// Style preset: classic, rustfmt, compact. Overrides the style set in config files
+    style: Option<Style>,
+
     /// Input file or dir.
     /// If `input` is a dir, rfmt will do action for all files in this dir recursively.
     /// If neither `options` nor `input` is specified, rfmt will format source code from stdin.
     #[structopt(parse(from_os_str))]
     input: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+impl Opt {
+    pub fn emit(&self) -> Emit {
+        if self.overwrite {
+            Emit::Files
+        } else if self.diff {
+            Emit::Diff
+        } else {
+            self.emit.unwrap_or(Emit::Stdout)
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    #[structopt(name = "config")]
+    /// Config file tools
+    Config(ConfigCommand),
+
+    #[structopt(name = "cache")]
+    /// Cache of formatted files tools
+    Cache(CacheCommand),
+
+    #[structopt(name = "lsp")]
+    /// Serve formatting to editors with the Language Server Protocol over stdin and stdout
+    Lsp,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ConfigCommand {
+    #[structopt(name = "import")]
+    /// Convert a rustfmt.toml to rfmt.toml and print it
+    Import {
+        /// The rustfmt.toml to convert.
+        /// If not specified, the rustfmt.toml or .rustfmt.toml in the current dir is converted.
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum CacheCommand {
+    #[structopt(name = "clear")]
+    /// Remove the cache of formatted files
+    Clear,
 }
 
 fn main() {
     let opt = Opt::from_args();
-    if opt.input.is_none() {
-        rfmt::fmt_from_stdin(opt);
+    let result = if let Some(cmd) = opt.cmd {
+        match cmd {
+            Command::Config(ConfigCommand::Import {input}) => cli::import_config(&input).map(|_| 0),
+            Command::Cache(CacheCommand::Clear) => cache::clear().map(|_| 0),
+            Command::Lsp => lsp::run(opt.style),
+        }
+    } else if opt.print_config {
+        cli::print_config(&opt.input, opt.style).map(|_| 0)
+    } else if opt.diff_filter {
+        cli::fmt_diff_filter(opt).map(|summary| summary.exit_code())
+    } else if opt.input.is_none() {
+        cli::fmt_from_stdin(opt).map(|summary| summary.exit_code())
     } else if opt.ast {
-        rfmt::dump_ast(&opt.input.unwrap());
+        cli::dump_ast(&opt.input.unwrap()).map(|_| 0)
     } else if opt.debug {
-        rfmt::debug(&opt.input.unwrap());
+        cli::debug(&opt.input.unwrap(), opt.style).map(|_| 0)
     } else if opt.print {
-        rfmt::print(&opt.input.unwrap());
+        cli::print(&opt.input.unwrap(), opt.style).map(|_| 0)
     } else {
-        rfmt::fmt(opt);
+        cli::fmt(opt).map(|summary| summary.exit_code())
+    };
+
+    match result {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        },
     }
 }
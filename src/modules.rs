@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use syntax::parse::ParseSess;
+use syntax::source_map::FilePathMapping;
+
+use crate::ast;
+use crate::error::{ErrorLoc, RfmtError};
+
+const PATH_ATTR: &str = "path";
+const MOD_FILE_NAME: &str = "mod.rs";
+
+// A module file and whether it owns its dir. A crate root, a mod.rs or a file of a `#[path]` owns its dir, the
+// modules it declares are in its dir. The modules declared by another foo.rs are in foo/.
+type ModFile = (PathBuf, bool);
+
+pub fn crate_files(roots: &[PathBuf]) -> Vec<Result<PathBuf, RfmtError>> {
+    let mut visited = HashSet::new();
+    let mut files = Vec::new();
+    for root in roots {
+        add_mod_file((root.clone(), true), &mut visited, &mut files);
+    }
+    files
+}
+
+fn add_mod_file(file: ModFile, visited: &mut HashSet<PathBuf>, files: &mut Vec<Result<PathBuf, RfmtError>>) {
+    if !visited.insert(file.0.clone()) {
+        return;
+    }
+
+    let decls = mod_decls(&file);
+    files.push(Ok(file.0));
+    for decl in decls {
+        match decl {
+            Ok(file) => add_mod_file(file, visited, files),
+            Err(e) => files.push(Err(e)),
+        }
+    }
+}
+
+// The files of the out of line modules declared in `file`, in order. A file which can not be read or parsed declares
+// nothing, formatting it reports the error.
+fn mod_decls(file: &ModFile) -> Vec<Result<ModFile, RfmtError>> {
+    let (ref path, owns_dir) = *file;
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(_) => return Vec::new(),
+    };
+
+    syntax::with_default_globals(|| {
+        let sess = ParseSess::new(FilePathMapping::empty());
+        let krate = match crate::parse_crate(&sess, path, src) {
+            Ok(krate) => krate,
+            Err(mut e) => {
+                e.cancel();
+                return Vec::new();
+            },
+        };
+
+        let file_dir = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        let mod_dir = if owns_dir {
+            file_dir.clone()
+        } else {
+            file_dir.join(path.file_stem().unwrap_or_default())
+        };
+        let mut decls = Vec::new();
+        collect_mod_decls(&sess, &krate.module, &file_dir, &mod_dir, false, &mut decls);
+        decls
+    })
+}
+
+// Like rustc, a `#[path]` is relative to the dir of the file, or to the dir of the inline module it is in.
+fn collect_mod_decls(sess: &ParseSess, module: &ast::Mod, file_dir: &Path, mod_dir: &Path, in_inline: bool,
+                     decls: &mut Vec<Result<ModFile, RfmtError>>) {
+    for item in &module.items {
+        let module = match item.node {
+            ast::ItemKind::Mod(ref module) => module,
+            _ => continue,
+        };
+        let name = item.ident.as_str().to_string();
+        let path_attr = path_attr(&item.attrs);
+
+        if module.inline {
+            let dir = mod_dir.join(path_attr.unwrap_or(name));
+            collect_mod_decls(sess, module, file_dir, &dir, true, decls);
+            continue;
+        }
+
+        let candidates = match path_attr {
+            Some(path) if in_inline => vec![(mod_dir.join(path), true)],
+            Some(path) => vec![(file_dir.join(path), true)],
+            None => {
+                let file = mod_dir.join(format!("{}.rs", name));
+                vec![(file, false), (mod_dir.join(&name).join(MOD_FILE_NAME), true)]
+            },
+        };
+        decls.push(match candidates.into_iter().find(|&(ref path, _)| path.is_file()) {
+            Some(file) => Ok(file),
+            None => {
+                let loc = ErrorLoc::from_span(sess, item.span);
+                Err(RfmtError::Parse(loc, format!("file not found for module `{}`", name)))
+            },
+        });
+    }
+}
+
+#[inline]
+fn path_attr(attrs: &[ast::Attribute]) -> Option<String> {
+    attrs.iter().find(|attr| {
+        let segments = &attr.path.segments;
+        segments.len() == 1 && &*segments[0].ident.as_str() == PATH_ATTR
+    }).and_then(|attr| attr.value_str()).map(|value| value.to_string())
+}
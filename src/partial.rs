@@ -0,0 +1,305 @@
+use std::cmp;
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use crate::config::NewlineStyle;
+use crate::ft::FtResult;
+use crate::ir::*;
+use crate::source_map::{self, Mapping};
+
+// A node which can be formatted alone, its lines are replaced by its output.
+#[derive(Clone, Copy)]
+enum Unit<'a> {
+    Item(&'a Item),
+    ImplItem(&'a ImplItem),
+    TraitItem(&'a TraitItem),
+    Stmt(&'a Stmt),
+}
+
+impl<'a> Unit<'a> {
+    #[inline]
+    fn loc(self) -> Loc {
+        match self {
+            Unit::Item(item) => item.loc,
+            Unit::ImplItem(item) => item.loc,
+            Unit::TraitItem(item) => item.loc,
+            Unit::Stmt(stmt) => stmt.loc,
+        }
+    }
+
+    // The start of the node with its attributes, the loc of a node does not include them.
+    fn start(self) -> Pos {
+        let attrs = match self {
+            Unit::Item(item) => &item.attrs,
+            Unit::ImplItem(item) => &item.attrs,
+            Unit::TraitItem(item) => &item.attrs,
+            Unit::Stmt(stmt) => match stmt.stmt {
+                StmtKind::Item(ref item) => &item.attrs,
+                StmtKind::Let(ref local) => &local.attrs,
+                StmtKind::Expr(ref expr, _) => &expr.attrs,
+                StmtKind::Macro(ref mac) => &mac.attrs,
+                StmtKind::Skip(_) => return stmt.loc.start,
+            },
+        };
+        attrs.iter().map(|attr| match *attr {
+            AttrKind::Doc(ref doc) => doc.loc.start,
+            AttrKind::Attr(ref attr) => attr.loc.start,
+        }).fold(self.loc().start, cmp::min)
+    }
+
+    // `extern crate`, `use` and `mod` declarations are moved and sorted with their group, they are not formatted alone.
+    #[inline]
+    fn is_grouped(self) -> bool {
+        match self {
+            Unit::Item(item) => match item.item {
+                ItemKind::ExternCrate(_) | ItemKind::Use(_) | ItemKind::ModDecl(_) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn children(self) -> Vec<Unit<'a>> {
+        match self {
+            Unit::Item(item) => match item.item {
+                ItemKind::Mod(ref module) => module.items.iter().map(Unit::Item).collect(),
+                ItemKind::Fn(ref item) => stmt_units(&item.block),
+                ItemKind::Impl(ref item) => item.items.iter().map(Unit::ImplItem).collect(),
+                ItemKind::Trait(ref item) => item.items.iter().map(Unit::TraitItem).collect(),
+                _ => Vec::new(),
+            },
+            Unit::ImplItem(item) => match item.item {
+                ImplItemKind::Method(ref item) => stmt_units(&item.block),
+                _ => Vec::new(),
+            },
+            Unit::TraitItem(item) => match item.item {
+                TraitItemKind::Method(MethodTraitItem {block: Some(ref block), ..}) => stmt_units(block),
+                _ => Vec::new(),
+            },
+            Unit::Stmt(stmt) => match stmt.stmt {
+                StmtKind::Item(ref item) => Unit::Item(item).children(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+#[inline]
+fn stmt_units(block: &Block) -> Vec<Unit> {
+    block.stmts.iter().map(Unit::Stmt).collect()
+}
+
+// A selected node: its loc, and its lines in the source.
+pub struct Selected {
+    pub loc: Loc,
+    pub lines: Range<usize>,
+    // The enclosing nodes which are alone in their lines, outermost first. One of them is formatted instead if the
+    // node has no output span, for example the statement of a block formatted in one line.
+    pub parents: Vec<(Loc, Range<usize>)>,
+}
+
+struct Selector<'a> {
+    src: &'a str,
+    ranges: &'a [Range<usize>],
+    selected: Vec<Selected>,
+    parents: Vec<(Loc, Range<usize>)>,
+}
+
+impl<'a> Selector<'a> {
+    // Selects the innermost units which overlap the ranges. Returns false if one of them is not alone in its lines, so
+    // its parent must be selected instead.
+    fn select(&mut self, units: &[Unit]) -> bool {
+        for &unit in units {
+            let lines = self.lines(unit.start(), unit.loc().end);
+            if !self.overlaps(&lines) || unit.is_grouped() {
+                continue;
+            }
+
+            let children = unit.children();
+            if let (Some(first), Some(last)) = (children.first(), children.last()) {
+                let child_lines = self.lines(first.start(), last.loc().end);
+                if self.ranges.iter().filter(|range| overlaps(range, &lines)).all(|range| {
+                    child_lines.start <= range.start && range.end <= child_lines.end
+                }) {
+                    let len = self.selected.len();
+                    let alone = self.is_alone(unit.start(), unit.loc().end);
+                    if alone {
+                        self.parents.push((unit.loc(), lines.clone()));
+                    }
+                    let done = self.select(&children);
+                    if alone {
+                        self.parents.pop();
+                    }
+                    if done {
+                        continue;
+                    }
+                    self.selected.truncate(len);
+                }
+            }
+
+            if !self.is_alone(unit.start(), unit.loc().end) {
+                return false;
+            }
+            self.selected.push(Selected {
+                loc: unit.loc(),
+                lines,
+                parents: self.parents.clone(),
+            });
+        }
+        true
+    }
+
+    #[inline]
+    fn overlaps(&self, lines: &Range<usize>) -> bool {
+        self.ranges.iter().any(|range| overlaps(range, lines))
+    }
+
+    // `pos` in `src`, at the char boundary before it.
+    #[inline]
+    fn offset(&self, pos: Pos) -> usize {
+        let pos = cmp::min(pos as usize, self.src.len());
+        (0..=pos).rev().find(|&i| self.src.is_char_boundary(i)).unwrap_or(0)
+    }
+
+    // From the start of the line of `start` to the end of the line of `end`, with its newline.
+    #[inline]
+    fn lines(&self, start: Pos, end: Pos) -> Range<usize> {
+        let start = self.offset(start);
+        let end = self.offset(end);
+        let line_start = self.src[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = self.src[end..].find('\n').map_or(self.src.len(), |pos| end + pos + 1);
+        line_start..line_end
+    }
+
+    // Nothing else is in the lines of the node, except a comment at the end.
+    fn is_alone(&self, start: Pos, end: Pos) -> bool {
+        let lines = self.lines(start, end);
+        if !self.src[lines.start..self.offset(start)].trim().is_empty() {
+            return false;
+        }
+
+        let rest = self.src[self.offset(end)..lines.end].trim();
+        if rest.is_empty() || rest.starts_with("//") {
+            return true;
+        }
+        rest.starts_with("/*") && rest.find("*/").map_or(false, |pos| rest[pos + 2..].trim().is_empty())
+    }
+}
+
+// An empty range is a position, it overlaps the line it is in.
+#[inline]
+fn overlaps(range: &Range<usize>, lines: &Range<usize>) -> bool {
+    range.start < lines.end && lines.start < cmp::max(range.end, range.start + 1)
+}
+
+// The nodes to format for the byte `ranges` of `src`, in order. Returns `None` if a top level node is not alone in its
+// lines, nothing can be formatted alone then.
+pub fn select(src: &str, krate: &Crate, ranges: &[Range<usize>]) -> Option<Vec<Selected>> {
+    let mut selector = Selector {
+        src,
+        ranges,
+        selected: Vec::new(),
+        parents: Vec::new(),
+    };
+    let units: Vec<_> = krate.module.items.iter().map(Unit::Item).collect();
+    if !selector.select(&units) {
+        return None;
+    }
+
+    let mut selected = selector.selected;
+    selected.sort_by_key(|selected| selected.lines.start);
+    Some(selected)
+}
+
+pub struct Spliced {
+    pub s: String,
+    pub exceed_lines: BTreeSet<u32>,
+    pub trailing_ws_lines: BTreeSet<u32>,
+    // The mappings of the nodes of the outputs, and of the lines copied as is.
+    pub mappings: Vec<Mapping>,
+    // The lines of the source which are replaced.
+    pub lines: Vec<Range<usize>>,
+}
+
+// Replaces the lines of every selected node in `src` by its output, or the lines of its innermost parent with an
+// output span if it has none. The exceed and trailing white space lines of the outputs are moved to their lines in the
+// result. Returns `None` if neither a node nor its parents have an output span.
+pub fn splice(src: &str, selected: &[Selected], ft_result: &FtResult, newline_style: NewlineStyle) -> Option<Spliced> {
+    let mut spliced = Spliced {
+        s: String::new(),
+        exceed_lines: BTreeSet::new(),
+        trailing_ws_lines: BTreeSet::new(),
+        mappings: Vec::new(),
+        lines: Vec::new(),
+    };
+    let mut nodes = Vec::new();
+    for selected in selected {
+        let mut nodes_of = Some((selected.loc, selected.lines.clone())).into_iter()
+                                                                       .chain(selected.parents.iter().rev().cloned());
+        let node = nodes_of.find_map(|(loc, lines)| {
+            ft_result.node_spans.get(&(loc.start, loc.end)).map(|&span| (lines, span))
+        })?;
+        nodes.push(node);
+    }
+    // The lines of two nodes are either disjoint or nested, a parent comes before the nodes it contains.
+    nodes.sort_by(|a, b| a.0.start.cmp(&b.0.start).then(b.0.end.cmp(&a.0.end)));
+
+    let mut copied = 0;
+    for (lines, (start, end)) in nodes {
+        if lines.end <= copied {
+            continue;
+        }
+        copy(&mut spliced, src, copied..lines.start);
+
+        let out = &ft_result.s[start..end];
+        let out_line = crate::line_of(&ft_result.s, start);
+        let out_lines = out_line..out_line + out.matches('\n').count() as u32;
+        let line = crate::line_of(&spliced.s, spliced.s.len());
+        let move_line = |out: &u32| out - out_line + line;
+        spliced.exceed_lines.extend(ft_result.exceed_lines.range(out_lines.clone()).map(move_line));
+        spliced.trailing_ws_lines.extend(ft_result.trailing_ws_lines.range(out_lines).map(move_line));
+
+        let converted = newline_style.apply(src, out.to_string());
+        let crlf = converted.len() != out.len();
+        let out_start = spliced.s.len();
+        let mappings = source_map::move_mappings(&ft_result.mappings, &ft_result.s, start..end, out_start, crlf);
+        spliced.mappings.extend(mappings);
+        spliced.s.push_str(&converted);
+        copied = lines.end;
+        spliced.lines.push(lines);
+    }
+    copy(&mut spliced, src, copied..src.len());
+    Some(spliced)
+}
+
+#[inline]
+fn copy(spliced: &mut Spliced, src: &str, range: Range<usize>) {
+    if range.start == range.end {
+        return;
+    }
+
+    let out = spliced.s.len()..spliced.s.len() + range.len();
+    spliced.s.push_str(&src[range.clone()]);
+    spliced.mappings.push(Mapping {
+        src: range,
+        out,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_alone_clamps_the_positions() {
+        let selector = Selector {
+            src: "é\nfn f() {}",
+            ranges: &[],
+            selected: Vec::new(),
+            parents: Vec::new(),
+        };
+        assert!(selector.is_alone(1, 2));
+        assert!(selector.is_alone(3, 40));
+        assert!(!selector.is_alone(6, 40));
+    }
+}
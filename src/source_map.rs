@@ -0,0 +1,130 @@
+use std::cmp;
+use std::ops::Range;
+
+/// The byte range of a node in the source, and of its output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub src: Range<usize>,
+    pub out: Range<usize>,
+}
+
+/// The mappings of the expressions, patterns, types, statements, blocks and items of a formatted source, and of the
+/// lines which are copied as is, ordered by the start of their source.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceMap {
+    pub mappings: Vec<Mapping>,
+    pub out_len: usize,
+}
+
+impl SourceMap {
+    pub(crate) fn new(mut mappings: Vec<Mapping>, out_len: usize) -> SourceMap {
+        mappings.sort_by(|a, b| a.src.start.cmp(&b.src.start).then(b.src.end.cmp(&a.src.end)));
+        SourceMap {
+            mappings,
+            out_len,
+        }
+    }
+
+    /// The output offset of the source `offset`, for example to keep the cursor where it was. In the innermost node
+    /// which contains `offset`, it is counted from the end of the last node before it, or from the start of the node.
+    pub fn map_offset(&self, offset: usize) -> usize {
+        let container = self.mappings.iter().filter(|m| m.src.start <= offset && offset < m.src.end)
+                                            .min_by_key(|m| m.src.end - m.src.start);
+        let (src, out) = match container {
+            Some(m) => (m.src.clone(), m.out.clone()),
+            None => (0..usize::max_value(), 0..self.out_len),
+        };
+
+        let before = self.mappings.iter().filter(|m| src.start <= m.src.start && m.src.end <= offset && m.src != src)
+                                         .max_by_key(|m| (m.src.end, m.src.end - m.src.start));
+        let anchor = match before {
+            Some(m) => (m.src.end, m.out.end),
+            None => (src.start, out.start),
+        };
+        let mapped = anchor.1 + (offset - anchor.0);
+        cmp::min(cmp::max(mapped, out.start), out.end)
+    }
+}
+
+// The `mappings` of the output `s` which are in `range`, moved to `out_start`. The newlines of `s[range]` are
+// converted to `\r\n` in the output if `crlf`.
+pub(crate) fn move_mappings(mappings: &[Mapping], s: &str, range: Range<usize>, out_start: usize, crlf: bool)
+-> Vec<Mapping> {
+    let lfs: Vec<usize> = if crlf {
+        s[range.clone()].match_indices('\n').map(|(pos, _)| range.start + pos)
+                        .filter(|&pos| pos == 0 || s.as_bytes()[pos - 1] != b'\r').collect()
+    } else {
+        Vec::new()
+    };
+    let offset = |pos: usize| -> usize {
+        let lfs_before = match lfs.binary_search(&pos) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        out_start + pos - range.start + lfs_before
+    };
+
+    mappings.iter().filter(|m| range.start <= m.out.start && m.out.end <= range.end).map(|m| Mapping {
+        src: m.src.clone(),
+        out: offset(m.out.start)..offset(m.out.end),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline]
+    fn mapping(src: Range<usize>, out: Range<usize>) -> Mapping {
+        Mapping { src, out }
+    }
+
+    // `a  +  b` formatted to `a + b`.
+    fn binary() -> SourceMap {
+        SourceMap::new(vec![mapping(6..7, 4..5), mapping(0..7, 0..5), mapping(0..1, 0..1)], 5)
+    }
+
+    #[test]
+    fn offset_in_a_node() {
+        let map = binary();
+        assert_eq!(map.map_offset(0), 0);
+        assert_eq!(map.map_offset(6), 4);
+    }
+
+    #[test]
+    fn offset_in_a_changed_node() {
+        let map = binary();
+        assert_eq!(map.map_offset(3), 3);
+        assert_eq!(map.map_offset(5), 5);
+    }
+
+    #[test]
+    fn offset_past_the_end() {
+        let map = binary();
+        assert_eq!(map.map_offset(7), 5);
+        assert_eq!(map.map_offset(100), 5);
+    }
+
+    #[test]
+    fn move_mappings_crlf() {
+        let s = "fn f() {\n    x\n}\n";
+        let mappings = [mapping(0..17, 0..17), mapping(3..4, 13..14)];
+        let moved = move_mappings(&mappings, s, 0..s.len(), 0, true);
+        assert_eq!(moved, vec![mapping(0..17, 0..20), mapping(3..4, 14..15)]);
+        assert_eq!(move_mappings(&mappings, s, 9..16, 100, true), vec![mapping(3..4, 104..105)]);
+        assert_eq!(move_mappings(&mappings, s, 9..16, 100, false), vec![mapping(3..4, 104..105)]);
+
+        let s = "a\r\nb";
+        assert_eq!(move_mappings(&[mapping(0..1, 3..4)], s, 0..s.len(), 0, true), vec![mapping(0..1, 3..4)]);
+    }
+
+    #[test]
+    fn cursor_after_reindent() {
+        let src = "fn f() {\n  let x = 1;\n  let y = x;\n}\n";
+        let formatted = crate::format_str(src, &crate::Config::default()).unwrap();
+        assert_eq!(formatted.s, "fn f() {\n    let x = 1;\n    let y = x;\n}\n");
+
+        let cursor = src.find('y').unwrap();
+        assert_eq!(formatted.source_map.map_offset(cursor), formatted.s.find('y').unwrap());
+        assert_eq!(formatted.source_map.map_offset(src.len()), formatted.s.len());
+    }
+}
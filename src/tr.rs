@@ -1,15 +1,18 @@
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeMap, HashMap};
 
 use syntax::ThinVec;
 use syntax::parse::ParseSess;
 
 use crate::ast;
+use crate::config::Config;
+use crate::error::{ErrorLoc, RfmtError};
 use crate::ir::*;
 
-const MAX_BLANK_LINE: u8 = 1;
+const RFMT_OFF: &str = "// rfmt-off";
+const RFMT_ON: &str = "// rfmt-on";
 
-fn trans_comments(cmnts: Vec<ast::Comment>) -> Vec<Comment> {
+fn trans_comments(cmnts: Vec<ast::Comment>, max_blank_lines: usize) -> Vec<Comment> {
     let mut pre_blank_line_pos = 0;
     let mut blank_line = 0;
 
@@ -22,7 +25,7 @@ fn trans_comments(cmnts: Vec<ast::Comment>) -> Vec<Comment> {
                 cmnts.push(trans_comment(cmnt));
             } else {
                 blank_line += 1;
-                if blank_line <= MAX_BLANK_LINE {
+                if blank_line <= max_blank_lines {
                     cmnts.push(trans_comment(cmnt));
                 }
             }
@@ -104,6 +107,30 @@ fn path_to_string(path: &ast::Path) -> String {
     })
 }
 
+#[inline]
+fn is_skip(attrs: &[ast::Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        let path = path_to_string(&attr.path);
+        path == "rustfmt::skip" || path == "rfmt::skip"
+    })
+}
+
+#[inline]
+fn skip_span(attrs: &[ast::Attribute], sp: ast::Span) -> ast::Span {
+    let lo = attrs.iter().map(|attr| attr.span.lo().0).fold(sp.lo().0, cmp::min);
+    span(lo, sp.hi().0)
+}
+
+#[inline]
+fn stmt_attrs(stmt: &ast::Stmt) -> &[ast::Attribute] {
+    match stmt.node {
+        ast::StmtKind::Local(ref local) => &local.attrs,
+        ast::StmtKind::Item(ref item) => &item.attrs,
+        ast::StmtKind::Semi(ref expr) | ast::StmtKind::Expr(ref expr) => &expr.attrs,
+        ast::StmtKind::Mac(ref p) => &p.2,
+    }
+}
+
 #[inline]
 fn is_sized(modifier: ast::TraitBoundModifier) -> bool {
     modifier == ast::TraitBoundModifier::Maybe
@@ -175,6 +202,14 @@ fn is_default(defaultness: ast::Defaultness) -> bool {
     defaultness == ast::Defaultness::Default
 }
 
+#[inline]
+fn is_block_expr(expr: &ast::Expr) -> bool {
+    match expr.node {
+        ast::ExprKind::Block(..) => true,
+        _ => false,
+    }
+}
+
 #[inline]
 fn is_block_unsafe(rules: ast::BlockCheckMode) -> bool {
     match rules {
@@ -238,19 +273,19 @@ fn macro_end(token: &ast::TokenTree) -> u32 {
 }
 
 #[inline]
-fn token_to_macro_sep(token: &ast::TokenKind) -> MacroSep {
+fn token_to_macro_sep(token: &ast::TokenKind) -> Option<MacroSep> {
     let (is_sep, s) = match token {
         ast::TokenKind::Comma => (true, ","),
         ast::TokenKind::Semi => (true, ";"),
         ast::TokenKind::FatArrow => (true, " =>"),
         ast::TokenKind::DotDotDot => (false, "..."),
-        _ => unreachable!("{:?}", token),
+        _ => return None,
     };
 
-    MacroSep {
+    Some(MacroSep {
         is_sep,
         s,
-    }
+    })
 }
 
 #[inline]
@@ -275,17 +310,47 @@ macro_rules! trans_list {
     });
 }
 
+macro_rules! trans_off_list {
+    ($sf: ident, $list: ident, $trans_single: ident, $trans_skip: ident) => ({
+        let mut list = Vec::new();
+        let mut i = 0;
+        while i < $list.len() {
+            match $sf.off_region($list[i].span.lo().0) {
+                Some((mut start, mut end)) => {
+                    if start < $sf.last_loc.end {
+                        start = $list[i].span.lo().0;
+                    }
+                    while i < $list.len() && $list[i].span.lo().0 < end {
+                        end = cmp::max(end, $list[i].span.hi().0);
+                        i += 1;
+                    }
+                    list.push($sf.$trans_skip(&[], span(start, end)));
+                },
+                None => {
+                    list.push($sf.$trans_single(&$list[i]));
+                    i += 1;
+                },
+            }
+        }
+        list
+    });
+}
+
 pub struct TrResult {
     pub krate: Crate,
     pub leading_cmnts: HashMap<Pos, Vec<String>>,
     pub trailing_cmnts: HashMap<Pos, String>,
+    pub brace_cmnts: BTreeMap<Pos, String>,
+    pub warnings: Vec<String>,
 }
 
-pub fn trans(src: String, sess: ParseSess, krate: ast::Crate, cmnts: Vec<ast::Comment>) -> TrResult {
-    Translator::new(src, sess, trans_comments(cmnts)).trans_crate(krate)
+pub fn trans(src: String, sess: ParseSess, krate: ast::Crate, cmnts: Vec<ast::Comment>, config: &Config)
+-> Result<TrResult, RfmtError> {
+    Translator::new(src, sess, trans_comments(cmnts, config.max_blank_lines), config).trans_crate(krate)
 }
 
 struct Translator {
+    reorder_imports: bool,
     src: String,
     sess: ParseSess,
     cmnts: Vec<Comment>,
@@ -293,11 +358,16 @@ struct Translator {
     last_loc: Loc,
     leading_cmnts: HashMap<Pos, Vec<String>>,
     trailing_cmnts: HashMap<Pos, String>,
+    brace_cmnts: BTreeMap<Pos, String>,
+    off_regions: Vec<(Pos, Pos)>,
+    warnings: Vec<String>,
+    error: Option<RfmtError>,
 }
 
 impl Translator {
-    fn new(src: String, sess: ParseSess, cmnts: Vec<Comment>) -> Translator {
+    fn new(src: String, sess: ParseSess, cmnts: Vec<Comment>, config: &Config) -> Translator {
         Translator {
+            reorder_imports: config.reorder_imports,
             src,
             sess,
             cmnts,
@@ -305,11 +375,16 @@ impl Translator {
             last_loc: Default::default(),
             leading_cmnts: HashMap::new(),
             trailing_cmnts: HashMap::new(),
+            brace_cmnts: BTreeMap::new(),
+            off_regions: Vec::new(),
+            warnings: Vec::new(),
+            error: None,
         }
     }
 
-    fn trans_crate(mut self, krate: ast::Crate) -> TrResult {
+    fn trans_crate(mut self, krate: ast::Crate) -> Result<TrResult, RfmtError> {
         self.last_loc.start = krate.span.lo().0;
+        self.trans_off_regions();
 
         let loc = self.loc(&krate.span);
         let attrs = self.trans_attrs(&krate.attrs);
@@ -319,7 +394,11 @@ impl Translator {
         let crate_file_end = self.crate_file_end();
         self.trans_comments(crate_file_end);
 
-        TrResult {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        Ok(TrResult {
             krate: Crate {
                 loc,
                 attrs,
@@ -327,7 +406,67 @@ impl Translator {
             },
             leading_cmnts: self.leading_cmnts,
             trailing_cmnts: self.trailing_cmnts,
+            brace_cmnts: self.brace_cmnts,
+            warnings: self.warnings,
+        })
+    }
+
+    fn trans_off_regions(&mut self) {
+        let mut regions = Vec::new();
+        let mut warnings = Vec::new();
+        let mut off = None;
+        for cmnt in &self.cmnts {
+            if cmnt.kind != CommentKind::Leading {
+                continue;
+            }
+
+            if cmnt.lines.iter().any(|line| line.trim() == RFMT_OFF) {
+                if off.is_some() {
+                    warnings.push(format!("line {}: `{}` inside an off region", self.line(cmnt.pos), RFMT_OFF));
+                } else {
+                    off = Some(cmnt.pos);
+                }
+            } else if cmnt.lines.iter().any(|line| line.trim() == RFMT_ON) {
+                match off.take() {
+                    Some(start) => regions.push((start, self.comment_end(cmnt))),
+                    None => {
+                        warnings.push(format!("line {}: `{}` without a matching `{}`", self.line(cmnt.pos), RFMT_ON,
+                                              RFMT_OFF));
+                    },
+                }
+            }
+        }
+
+        if let Some(start) = off {
+            warnings.push(format!("line {}: `{}` without a matching `{}`", self.line(start), RFMT_OFF, RFMT_ON));
+            regions.push((start, self.crate_file_end()));
         }
+
+        self.off_regions = regions;
+        self.warnings = warnings;
+    }
+
+    #[inline]
+    fn off_region(&self, pos: Pos) -> Option<(Pos, Pos)> {
+        self.off_regions.iter().find(|&&(start, end)| start <= pos && pos < end).cloned()
+    }
+
+    #[inline]
+    fn comment_end(&self, cmnt: &Comment) -> Pos {
+        let mut end = cmnt.pos as usize;
+        for i in 0..cmnt.lines.len() {
+            match self.src[end..].find('\n') {
+                Some(len) if i + 1 < cmnt.lines.len() => end += len + 1,
+                Some(len) => end += len,
+                None => end = self.src.len(),
+            }
+        }
+        self.src[..end].trim_end_matches('\r').len() as Pos
+    }
+
+    #[inline]
+    fn line(&self, pos: Pos) -> usize {
+        self.src[..pos as usize].matches('\n').count() + 1
     }
 
     #[inline]
@@ -349,11 +488,57 @@ impl Translator {
         }
         self.cmnt_idx += 1;
 
-        self.trailing_cmnts.insert(self.last_loc.end, cmnt.lines[0].clone());
+        match self.brace_pos(cmnt.pos) {
+            Some(brace_pos) => self.brace_cmnts.insert(brace_pos, cmnt.lines[0].clone()),
+            None => self.trailing_cmnts.insert(self.last_loc.end, cmnt.lines[0].clone()),
+        };
         cmnts.extend_from_slice(&cmnt.lines[1..]);
         cmnts
     }
 
+    // Pos of the `{` if the comment is right after it, like `struct A { // comment`.
+    #[inline]
+    fn brace_pos(&self, pos: Pos) -> Option<Pos> {
+        let line_start = self.src[..pos as usize].rfind('\n').map_or(0, |idx| idx + 1);
+        let code = self.src[line_start..pos as usize].trim_end();
+        if code.ends_with('{') {
+            Some((line_start + code.len() - 1) as Pos)
+        } else {
+            None
+        }
+    }
+
+    // Comments between the last element of a list and the `close` of the list are trailing comments of the element,
+    // otherwise they would be leading comments of whatever is after the list.
+    fn trans_list_end_comments(&mut self, close: char) {
+        let mut pos = self.last_loc.end as usize;
+        let mut cmnt_idx = self.cmnt_idx;
+        loop {
+            let rest = &self.src[pos..];
+            pos += rest.len() - rest.trim_start_matches(|ch: char| ch.is_whitespace() || ch == ',').len();
+            if cmnt_idx < self.cmnts.len() && self.cmnts[cmnt_idx].pos as usize == pos {
+                pos = self.comment_end(&self.cmnts[cmnt_idx]) as usize;
+                cmnt_idx += 1;
+            } else {
+                break;
+            }
+        }
+        if cmnt_idx == self.cmnt_idx || !self.src[pos..].starts_with(close) {
+            return;
+        }
+
+        let mut cmnts: Vec<String> = self.trailing_cmnts.remove(&self.last_loc.end).into_iter().collect();
+        for cmnt in &self.cmnts[self.cmnt_idx..cmnt_idx] {
+            if !cmnt.lines.is_empty() {
+                cmnts.push(cmnt.lines.join(" "));
+            }
+        }
+        self.cmnt_idx = cmnt_idx;
+        if !cmnts.is_empty() {
+            self.trailing_cmnts.insert(self.last_loc.end, cmnts.join(" "));
+        }
+    }
+
     #[inline]
     fn trans_leading_comments(&mut self, pos: Pos, mut cmnts: Vec<String>) {
         while self.cmnt_idx < self.cmnts.len() {
@@ -404,7 +589,17 @@ impl Translator {
         let loc = self.loc(&attr.span);
         let is_inner = is_inner(attr.style);
         let span_forward = if is_inner { 2 } else { 1 };
-        let item = self.trans_meta_item(&attr.meta().unwrap(), span_forward);
+        let item = match attr.meta() {
+            Some(ref meta_item) => self.trans_meta_item(meta_item, span_forward),
+            None => {
+                self.error(attr.span, RfmtError::Unsupported, "attribute which is not a meta item");
+                MetaItem {
+                    loc,
+                    name: String::new(),
+                    items: None,
+                }
+            },
+        };
         self.set_loc(&loc);
 
         Attr {
@@ -494,11 +689,15 @@ impl Translator {
     }
 
     fn trans_items(&mut self, items: &Vec<ast::P<ast::Item>>) -> Vec<Item> {
-        trans_list!(self, items, trans_item)
+        trans_off_list!(self, items, trans_item, trans_skip_item)
     }
 
     #[inline]
     fn trans_item(&mut self, item: &ast::Item) -> Item {
+        if is_skip(&item.attrs) {
+            return self.trans_skip_item(&item.attrs, item.span);
+        }
+
         let loc = self.loc(&item.span);
         let attrs = self.trans_attrs(&item.attrs);
         let vis = self.trans_vis(&item.vis);
@@ -541,7 +740,7 @@ impl Translator {
             },
             ast::ItemKind::MacroDef(ref mac_def) => ItemKind::MacroDef(self.trans_macro_def(ident, mac_def)),
             ast::ItemKind::Mac(ref mac) => ItemKind::Macro(self.trans_macro(mac)),
-            ast::ItemKind::GlobalAsm(..) => unimplemented!("ast::ItemKind::GlobalAsm"),
+            ast::ItemKind::GlobalAsm(..) => ItemKind::Skip(self.unsupported(item.span, "global_asm!")),
         };
 
         self.set_loc(&loc);
@@ -553,6 +752,16 @@ impl Translator {
         }
     }
 
+    fn trans_skip_item(&mut self, attrs: &[ast::Attribute], sp: ast::Span) -> Item {
+        let skip = self.trans_skip(attrs, sp);
+        Item {
+            loc: skip.loc,
+            attrs: Vec::new(),
+            vis: String::new(),
+            item: ItemKind::Skip(skip),
+        }
+    }
+
     #[inline]
     fn trans_vis(&mut self, vis: &ast::Visibility) -> Vis {
         let vis = match vis.node {
@@ -639,6 +848,10 @@ impl Translator {
 
     fn trans_use_trees(&mut self, trees: &Vec<(ast::UseTree, ast::NodeId)>) -> Vec<UseTree> {
         let mut trees: Vec<UseTree> = trees.iter().map(|ref e| self.trans_use_tree(&e.0)).collect();
+        if !self.reorder_imports {
+            return trees;
+        }
+
         trees.sort_by(|a, b| {
             if a.path.starts_with("self") {
                 Ordering::Less
@@ -678,13 +891,35 @@ impl Translator {
     }
 
     fn trans_generics(&mut self, generics: &ast::Generics) -> Generics {
+        let mut result = self.trans_generic_params(generics);
+        result.wh = self.trans_where(&generics.where_clause.predicates);
+        result
+    }
+
+    // The where clause of a fn is after its args, so it is translated separately to keep the comments in order.
+    fn trans_generic_params(&mut self, generics: &ast::Generics) -> Generics {
+        let lifetime_defs = self.trans_lifetime_defs(&generics.params);
+        let type_params = self.trans_type_params(&generics.params);
+        if !lifetime_defs.is_empty() || !type_params.is_empty() {
+            self.trans_list_end_comments('>');
+        }
+
         Generics {
-            lifetime_defs: self.trans_lifetime_defs(&generics.params),
-            type_params: self.trans_type_params(&generics.params),
-            wh: self.trans_where(&generics.where_clause.predicates),
+            lifetime_defs,
+            type_params,
+            wh: Where {
+                clauses: Vec::new(),
+            },
         }
     }
 
+    fn trans_generics_and_fn_sig(&mut self, generics: &ast::Generics, decl: &ast::FnDecl) -> (Generics, FnSig) {
+        let mut result = self.trans_generic_params(generics);
+        let sig = self.trans_fn_sig(decl);
+        result.wh = self.trans_where(&generics.where_clause.predicates);
+        (result, sig)
+    }
+
     fn trans_lifetime_defs(&mut self, params: &Vec<ast::GenericParam>) -> Vec<LifetimeDef> {
         params.into_iter().fold(Vec::new(), |mut lifetime_defs, param| {
             if let ast::GenericParamKind::Lifetime = param.kind {
@@ -696,10 +931,14 @@ impl Translator {
 
     fn trans_lifetime_def(&mut self, param: &ast::GenericParam) -> LifetimeDef {
         let lifetime = self.trans_lifetime(&param.ident);
+        let bounds = self.trans_lifetimes(&param.bounds);
         LifetimeDef {
-            loc: lifetime.loc,
+            loc: Loc {
+                end: bounds.last().map_or(lifetime.loc.end, |bound| bound.loc.end),
+                ..lifetime.loc
+            },
             lifetime,
-            bounds: self.trans_lifetimes(&param.bounds),
+            bounds,
         }
     }
 
@@ -736,6 +975,10 @@ impl Translator {
             ast::GenericParamKind::Type { ref default, } => map_ref_mut(default, |ty| self.trans_type(ty)),
             _ => None,
         };
+        let loc = Loc {
+            end: cmp::max(loc.end, self.last_loc.end),
+            ..loc
+        };
         self.set_loc(&loc);
 
         TypeParam {
@@ -799,7 +1042,7 @@ impl Translator {
         match *predicate {
             ast::WherePredicate::RegionPredicate(ref region) => self.trans_where_lifetime(region),
             ast::WherePredicate::BoundPredicate(ref bound) => self.trans_where_bound(bound),
-            ast::WherePredicate::EqPredicate(..) => unimplemented!("ast::WherePredicate::EqPredicate"),
+            ast::WherePredicate::EqPredicate(ref eq) => self.trans_where_eq(eq),
         }
     }
 
@@ -819,6 +1062,18 @@ impl Translator {
         }
     }
 
+    fn trans_where_eq(&mut self, eq: &ast::WhereEqPredicate) -> WhereClause {
+        self.error(eq.span, RfmtError::Unsupported, "equality constraint in where clause");
+        WhereClause {
+            loc: self.leaf_loc(&eq.span),
+            clause: WhereKind::Bound(WhereBound {
+                lifetime_defs: Vec::new(),
+                ty: self.trans_type(&eq.lhs_ty),
+                bounds: TypeParamBounds(Vec::new()),
+            }),
+        }
+    }
+
     fn trans_where_bound(&mut self, bound: &ast::WhereBoundPredicate) -> WhereClause {
         let loc = self.loc(&bound.span);
         let lifetime_defs = self.trans_lifetime_defs(&bound.bound_generic_params);
@@ -974,8 +1229,14 @@ impl Translator {
                 TypeKind::BareFn(Box::new(self.trans_bare_fn_type(bare_fn)))
             },
             ast::TyKind::Mac(ref mac) => TypeKind::Macro(self.trans_macro(mac)),
-            ast::TyKind::Typeof(..) => unimplemented!("ast::TyKind::Typeof"),
-            ast::TyKind::Err => unreachable!("ast::TyKind::Err"),
+            ast::TyKind::Typeof(..) => {
+                self.error(ty.span, RfmtError::Unsupported, "typeof");
+                TypeKind::Symbol("_")
+            },
+            ast::TyKind::Err => {
+                self.error(ty.span, RfmtError::Parse, "invalid type");
+                TypeKind::Symbol("_")
+            },
         };
 
         self.set_loc(&loc);
@@ -1185,17 +1446,25 @@ impl Translator {
     }
 
     fn trans_args(&mut self, inputs: &Vec<ast::Arg>) -> Vec<Arg> {
-        trans_list!(self, inputs, trans_arg)
+        let args: Vec<Arg> = trans_list!(self, inputs, trans_arg);
+        if !args.is_empty() {
+            self.trans_list_end_comments(')');
+        }
+        args
     }
 
     #[inline]
     fn trans_arg(&mut self, arg: &ast::Arg) -> Arg {
         let patten = self.trans_patten(&arg.pat);
         let has_patten = has_patten(arg, &patten);
+        let ty = self.trans_type(&arg.ty);
         Arg {
-            loc: patten.loc.clone(),
+            loc: Loc {
+                end: ty.loc.end,
+                ..patten.loc
+            },
             patten,
-            ty: self.trans_type(&arg.ty),
+            ty,
             has_patten,
         }
     }
@@ -1264,10 +1533,11 @@ impl Translator {
     }
 
     fn trans_foreign_fn(&mut self, ident: String, generics: &ast::Generics, decl: &ast::FnDecl) -> ForeignFn {
+        let (generics, sig) = self.trans_generics_and_fn_sig(generics, decl);
         ForeignFn {
             name: ident,
-            sig: self.trans_fn_sig(decl),
-            generics: self.trans_generics(generics),
+            sig,
+            generics,
         }
     }
 
@@ -1282,11 +1552,13 @@ impl Translator {
 
     fn trans_fn(&mut self, header: &ast::FnHeader, ident: String, generics: &ast::Generics,
                 decl: &ast::FnDecl, block: &ast::Block) -> Fn {
+        let header = self.trans_fn_header(header);
+        let (generics, sig) = self.trans_generics_and_fn_sig(generics, decl);
         Fn {
-            header: self.trans_fn_header(header),
+            header,
             name: ident,
-            sig: self.trans_fn_sig(decl),
-            generics: self.trans_generics(generics),
+            sig,
+            generics,
             block: self.trans_block(block),
         }
     }
@@ -1304,11 +1576,15 @@ impl Translator {
     }
 
     fn trans_trait_items(&mut self, items: &Vec<ast::TraitItem>) -> Vec<TraitItem> {
-        trans_list!(self, items, trans_trait_item)
+        trans_off_list!(self, items, trans_trait_item, trans_skip_trait_item)
     }
 
     #[inline]
     fn trans_trait_item(&mut self, item: &ast::TraitItem) -> TraitItem {
+        if is_skip(&item.attrs) {
+            return self.trans_skip_trait_item(&item.attrs, item.span);
+        }
+
         let loc = self.loc(&item.span);
         let attrs = self.trans_attrs(&item.attrs);
         let ident = ident_to_string(&item.ident);
@@ -1333,6 +1609,15 @@ impl Translator {
         }
     }
 
+    fn trans_skip_trait_item(&mut self, attrs: &[ast::Attribute], sp: ast::Span) -> TraitItem {
+        let skip = self.trans_skip(attrs, sp);
+        TraitItem {
+            loc: skip.loc,
+            attrs: Vec::new(),
+            item: TraitItemKind::Skip(skip),
+        }
+    }
+
     fn trans_const_trait_item(&mut self, ident: String, ty: &ast::Ty, expr: &Option<ast::P<ast::Expr>>)
     -> ConstTraitItem {
         ConstTraitItem {
@@ -1362,11 +1647,13 @@ impl Translator {
     }
 
     fn trans_method_sig(&mut self, ident: String, generics: &ast::Generics, sig: &ast::MethodSig) -> MethodSig {
+        let header = self.trans_fn_header(&sig.header);
+        let (generics, fn_sig) = self.trans_generics_and_fn_sig(generics, &sig.decl);
         MethodSig {
-            header: self.trans_fn_header(&sig.header),
+            header,
             name: ident,
-            sig: self.trans_fn_sig(&sig.decl),
-            generics: self.trans_generics(generics),
+            sig: fn_sig,
+            generics,
         }
     }
 
@@ -1385,11 +1672,15 @@ impl Translator {
     }
 
     fn trans_impl_items(&mut self, items: &Vec<ast::ImplItem>) -> Vec<ImplItem> {
-        trans_list!(self, items, trans_impl_item)
+        trans_off_list!(self, items, trans_impl_item, trans_skip_impl_item)
     }
 
     #[inline]
     fn trans_impl_item(&mut self, item: &ast::ImplItem) -> ImplItem {
+        if is_skip(&item.attrs) {
+            return self.trans_skip_impl_item(&item.attrs, item.span);
+        }
+
         let loc = self.loc(&item.span);
         let attrs = self.trans_attrs(&item.attrs);
         let vis = self.trans_vis(&item.vis);
@@ -1421,6 +1712,17 @@ impl Translator {
         }
     }
 
+    fn trans_skip_impl_item(&mut self, attrs: &[ast::Attribute], sp: ast::Span) -> ImplItem {
+        let skip = self.trans_skip(attrs, sp);
+        ImplItem {
+            loc: skip.loc,
+            attrs: Vec::new(),
+            vis: String::new(),
+            is_default: false,
+            item: ImplItemKind::Skip(skip),
+        }
+    }
+
     fn trans_type_impl_item(&mut self, ident: String, generics: &ast::Generics, ty: &ast::Ty) -> TypeImplItem {
         TypeImplItem {
             name: ident,
@@ -1459,11 +1761,16 @@ impl Translator {
     }
 
     fn trans_stmts(&mut self, stmts: &Vec<ast::Stmt>) -> Vec<Stmt> {
-        trans_list!(self, stmts, trans_stmt)
+        trans_off_list!(self, stmts, trans_stmt, trans_skip_stmt)
     }
 
     #[inline]
     fn trans_stmt(&mut self, stmt: &ast::Stmt) -> Stmt {
+        let attrs = stmt_attrs(stmt);
+        if is_skip(attrs) {
+            return self.trans_skip_stmt(attrs, stmt.span);
+        }
+
         let loc = self.loc(&stmt.span);
         let stmt = match stmt.node {
             ast::StmtKind::Item(ref item) => StmtKind::Item(self.trans_item(item)),
@@ -1480,6 +1787,14 @@ impl Translator {
         }
     }
 
+    fn trans_skip_stmt(&mut self, attrs: &[ast::Attribute], sp: ast::Span) -> Stmt {
+        let skip = self.trans_skip(attrs, sp);
+        Stmt {
+            loc: skip.loc,
+            stmt: StmtKind::Skip(skip),
+        }
+    }
+
     fn trans_let(&mut self, local: &ast::Local) -> Let {
         let loc = self.loc(&local.span);
         let attrs = self.trans_thin_attrs(&local.attrs);
@@ -1536,7 +1851,10 @@ impl Translator {
                 PattenKind::Slice(Box::new(self.trans_slice_patten(pattens)))
             },
             ast::PatKind::Mac(ref mac) => PattenKind::Macro(self.trans_macro(mac)),
-            ast::PatKind::Box(..) => unreachable!("ast::PatKind::Box"),
+            ast::PatKind::Box(..) => {
+                self.error(patten.span, RfmtError::Unsupported, "box patten");
+                PattenKind::Wildcard
+            },
         };
         self.set_loc(&loc);
 
@@ -1625,6 +1943,15 @@ impl Translator {
     }
 
     fn trans_expr(&mut self, expr: &ast::Expr) -> Expr {
+        if is_skip(&expr.attrs) {
+            let skip = self.trans_skip(&expr.attrs, expr.span);
+            return Expr {
+                loc: skip.loc,
+                attrs: Vec::new(),
+                expr: ExprKind::Skip(skip),
+            };
+        }
+
         let loc = self.loc(&expr.span);
         let attrs = self.trans_thin_attrs(&expr.attrs);
         let expr = match expr.node {
@@ -1689,13 +2016,16 @@ impl Translator {
             },
             ast::ExprKind::Ret(ref expr) => ExprKind::Return(Box::new(self.trans_return_expr(expr))),
             ast::ExprKind::Mac(ref mac) => ExprKind::Macro(self.trans_macro(mac)),
-            ast::ExprKind::InlineAsm(..) => unimplemented!("ast::ExprKind::InlineAsm"),
-            ast::ExprKind::Box(..) => unreachable!("ast::ExprKind::Box"),
-            ast::ExprKind::Async(..) => unimplemented!("ast::ExprKind::Async"),
-            ast::ExprKind::Await(..) => unimplemented!("ast::ExprKind::Await"),
-            ast::ExprKind::TryBlock(..) => unimplemented!("ast::ExprKind::TryBlock"),
-            ast::ExprKind::Yield(..) => unimplemented!("ast::ExprKind::Yield"),
-            ast::ExprKind::Err => unreachable!("ast::ExprKind::Err"),
+            ast::ExprKind::InlineAsm(..) => ExprKind::Skip(self.unsupported(expr.span, "asm!")),
+            ast::ExprKind::Box(..) => ExprKind::Skip(self.unsupported(expr.span, "box expression")),
+            ast::ExprKind::Async(..) => ExprKind::Skip(self.unsupported(expr.span, "async block")),
+            ast::ExprKind::Await(..) => ExprKind::Skip(self.unsupported(expr.span, "await")),
+            ast::ExprKind::TryBlock(..) => ExprKind::Skip(self.unsupported(expr.span, "try block")),
+            ast::ExprKind::Yield(..) => ExprKind::Skip(self.unsupported(expr.span, "yield")),
+            ast::ExprKind::Err => {
+                self.error(expr.span, RfmtError::Parse, "invalid expression");
+                ExprKind::Skip(Default::default())
+            },
         };
         self.set_loc(&loc);
 
@@ -1907,6 +2237,33 @@ impl Translator {
 
     #[inline]
     fn trans_arm(&mut self, arm: &ast::Arm) -> Arm {
+        if is_skip(&arm.attrs) {
+            // a skipped arm has no pattens, the whole arm is kept in its body, with its comma if any
+            let hi = arm.body.span.hi().0;
+            let comma = self.src[hi as usize..].trim_start().starts_with(',');
+            let hi = if comma {
+                hi + self.src[hi as usize..].find(',').unwrap() as u32 + 1
+            } else {
+                hi
+            };
+            let mut skip = self.trans_skip(&arm.attrs, span(arm.pats[0].span.lo().0, hi));
+            // like the other arms, a comma is added after a body which is not a block
+            if !comma && !is_block_expr(&arm.body) {
+                skip.s.push(',');
+            }
+            return Arm {
+                loc: skip.loc,
+                attrs: Vec::new(),
+                pattens: Vec::new(),
+                guard: None,
+                body: Expr {
+                    loc: skip.loc,
+                    attrs: Vec::new(),
+                    expr: ExprKind::Skip(skip),
+                },
+            };
+        }
+
         let attrs = self.trans_attrs(&arm.attrs);
         let pattens = self.trans_pattens(&arm.pats);
         let guard = map_ref_mut(&arm.guard, |guard| self.trans_expr(guard));
@@ -1964,6 +2321,7 @@ impl Translator {
 
         MacroDef {
             name: ident,
+            def_pos: span.lo().0,
             def: self.span_to_snippet(span).unwrap(),
         }
     }
@@ -2000,7 +2358,13 @@ impl Translator {
     }
 
     fn trans_macro(&mut self, mac: &ast::Mac) -> Macro {
-        let (exprs, seps) = self.trans_macro_exprs(&mac.node.tts);
+        let (exprs, seps) = match self.trans_macro_exprs(&mac.node.tts) {
+            Ok(result) => result,
+            Err((sp, msg)) => {
+                self.error(sp.unwrap_or(mac.span), RfmtError::Macro, &msg);
+                (Vec::new(), Vec::new())
+            },
+        };
         let name = path_to_string(&mac.node.path);
         let style = self.macro_style(mac.span);
         let exprs = self.trans_exprs(&exprs);
@@ -2013,12 +2377,13 @@ impl Translator {
         }
     }
 
-    fn trans_macro_exprs(&self, ts: &ast::TokenStream) -> (Vec<ast::P<ast::Expr>>, Vec<MacroSep>) {
+    fn trans_macro_exprs(&self, ts: &ast::TokenStream)
+    -> Result<(Vec<ast::P<ast::Expr>>, Vec<MacroSep>), (Option<ast::Span>, String)> {
         let mut exprs = Vec::new();
         let mut seps = Vec::new();
 
         if ts.is_empty() {
-            return (exprs, seps);
+            return Ok((exprs, seps));
         }
 
         let mut parser = ast::parse::stream_to_parser(&self.sess, ts.clone(), None);
@@ -2026,14 +2391,21 @@ impl Translator {
             exprs.push(match parser.parse_expr() {
                 Ok(expr) => expr,
                 Err(mut e) => {
+                    let err = (e.span.primary_span(), e.message());
                     e.cancel();
-                    panic!()
+                    return Err(err);
                 },
             });
 
             match parser.token.kind {
                 ast::TokenKind::Eof => break,
-                ref other => seps.push(token_to_macro_sep(other)),
+                ref other => match token_to_macro_sep(other) {
+                    Some(sep) => seps.push(sep),
+                    None => {
+                        let snippet = self.span_to_snippet(parser.token.span).unwrap_or_default();
+                        return Err((Some(parser.token.span), format!("unexpected separator `{}`", snippet)));
+                    },
+                },
             }
 
             parser.bump();
@@ -2041,7 +2413,7 @@ impl Translator {
                 break;
             }
         }
-        (exprs, seps)
+        Ok((exprs, seps))
     }
 
     #[inline]
@@ -2061,6 +2433,37 @@ impl Translator {
         self.last_loc = *loc;
     }
 
+    #[inline]
+    fn trans_skip(&mut self, attrs: &[ast::Attribute], sp: ast::Span) -> Skip {
+        let sp = skip_span(attrs, sp);
+        let loc = self.loc(&sp);
+        while self.cmnt_idx < self.cmnts.len() && self.cmnts[self.cmnt_idx].pos < loc.end {
+            self.cmnt_idx += 1;
+        }
+        self.set_loc(&loc);
+
+        Skip {
+            loc,
+            s: self.span_to_snippet(sp).unwrap(),
+        }
+    }
+
+    #[inline]
+    fn unsupported(&mut self, sp: ast::Span, msg: &str) -> Skip {
+        self.error(sp, RfmtError::Unsupported, msg);
+        Skip {
+            loc: self.leaf_loc(&sp),
+            s: String::new(),
+        }
+    }
+
+    #[inline]
+    fn error(&mut self, sp: ast::Span, err: fn(ErrorLoc, String) -> RfmtError, msg: &str) {
+        if self.error.is_none() {
+            self.error = Some(err(ErrorLoc::from_span(&self.sess, sp), msg.to_string()));
+        }
+    }
+
     #[inline]
     fn leaf_loc(&mut self, sp: &ast::Span) -> Loc {
         let loc = self.loc(sp);
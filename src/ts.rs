@@ -1,14 +1,11 @@
 use std::collections::BTreeSet;
 use std::fmt::{self, Debug};
 
-const NL: char = '\n';
-
-const EXCEED_WIDTH: usize = 120;
-const MAX_WIDTH: usize = EXCEED_WIDTH - 1;
-const MAX_ALIGN_COL: usize = EXCEED_WIDTH / 3;
+use crate::config::Config;
+use crate::ir::Loc;
+use crate::source_map::Mapping;
 
-const INDENT: &'static str = "    ";
-const WRAP_INDENT: &'static str = "        ";
+const NL: char = '\n';
 
 #[macro_export]
 macro_rules! need_wrap {
@@ -26,10 +23,16 @@ macro_rules! need_nl_indent {
 
 macro_rules! raw_insert {
     ($sf:expr, $s:expr) => ({
+        raw_insert!($sf, $s, $s.len());
+    });
+
+    ($sf:expr, $s:expr, $width:expr) => ({
+        let start = $sf.s.len();
         $sf.s.push_str($s);
+        $sf.map_text(start);
 
-        $sf.col += $s.len();
-        if $sf.col > EXCEED_WIDTH {
+        $sf.col += $width;
+        if $sf.col > $sf.exceed_width {
             $sf.exceed_lines.insert($sf.line);
         }
     });
@@ -74,8 +77,29 @@ fn fill_str(ch: char, count: usize) -> String {
     s
 }
 
+#[inline]
+fn fill_indent(width: usize, tab_spaces: usize, hard_tabs: bool) -> String {
+    if hard_tabs {
+        let mut s = fill_str('\t', width / tab_spaces);
+        s.push_str(&fill_str(' ', width % tab_spaces));
+        s
+    } else {
+        fill_str(' ', width)
+    }
+}
+
 #[derive(Default)]
 pub struct Typesetter {
+    exceed_width: usize,
+    max_width: usize,
+    max_align_col: usize,
+    visual_align: bool,
+    tab_spaces: usize,
+    hard_tabs: bool,
+    indent_unit: String,
+    wrap_indent: String,
+    wrap_indent_width: usize,
+
     line: u32,
     col: usize,
     indent: String,
@@ -84,12 +108,19 @@ pub struct Typesetter {
     s: String,
     exceed_lines: BTreeSet<u32>,
     trailing_ws_lines: BTreeSet<u32>,
+
+    // The nodes being typeset, with the output offset of their first text once it is inserted. A node starts at its
+    // text, not at the wrap or the indent before it.
+    nodes: Vec<(Loc, Option<usize>)>,
+    text_end: usize,
+    mappings: Vec<Mapping>,
 }
 
 pub struct TsResult {
     pub s: String,
     pub exceed_lines: BTreeSet<u32>,
     pub trailing_ws_lines: BTreeSet<u32>,
+    pub mappings: Vec<Mapping>,
 }
 
 impl Debug for Typesetter {
@@ -104,8 +135,18 @@ impl Debug for Typesetter {
 }
 
 impl Typesetter {
-    pub fn new() -> Typesetter {
+    pub fn new(config: &Config) -> Typesetter {
         Typesetter {
+            exceed_width: config.max_width,
+            max_width: config.max_width - 1,
+            max_align_col: config.max_align_col,
+            visual_align: config.visual_align,
+            tab_spaces: config.tab_spaces,
+            hard_tabs: config.hard_tabs,
+            indent_unit: fill_indent(config.tab_spaces, config.tab_spaces, config.hard_tabs),
+            wrap_indent: fill_indent(config.wrap_indent, config.tab_spaces, config.hard_tabs),
+            wrap_indent_width: config.wrap_indent,
+
             line: 1,
             ..Default::default()
         }
@@ -116,12 +157,37 @@ impl Typesetter {
             s: self.s,
             exceed_lines: self.exceed_lines,
             trailing_ws_lines: self.trailing_ws_lines,
+            mappings: self.mappings,
         }
     }
 
+    #[inline]
+    pub fn begin_node(&mut self, loc: &Loc) {
+        self.nodes.push((*loc, None));
+    }
+
+    // Maps the loc of the node to its output, from its first text to its last one. A node without text is not mapped.
+    #[inline]
+    pub fn end_node(&mut self) {
+        if let Some((loc, Some(start))) = self.nodes.pop() {
+            self.mappings.push(Mapping {
+                src: loc.start as usize..loc.end as usize,
+                out: start..self.text_end,
+            });
+        }
+    }
+
+    // The byte offset of the next insert in the output.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.s.len()
+    }
+
     #[inline]
     pub fn force_insert(&mut self, s: &str) {
+        let start = self.s.len();
         self.s.push_str(s);
+        self.map_text(start);
     }
 
     #[inline]
@@ -140,18 +206,18 @@ impl Typesetter {
 
     #[inline]
     pub fn indent(&mut self) {
-        self.indent.push_str(INDENT);
+        self.indent.push_str(&self.indent_unit);
     }
 
     #[inline]
     pub fn outdent(&mut self) {
         let len = self.indent.len();
-        self.indent.truncate(len - INDENT.len());
+        self.indent.truncate(len - self.indent_unit.len());
     }
 
     #[inline]
     pub fn insert_indent(&mut self) {
-        raw_insert!(self, &self.indent);
+        raw_insert!(self, &self.indent, self.indent_width());
     }
 
     #[inline]
@@ -175,7 +241,12 @@ impl Typesetter {
 
     #[inline]
     pub fn can_one_line(&self, s: &str) -> bool {
-        self.left() > s.len()
+        self.can_one_line_len(s.len())
+    }
+
+    #[inline]
+    pub fn can_one_line_len(&self, len: usize) -> bool {
+        self.left() > len
     }
 
     #[inline]
@@ -236,20 +307,24 @@ impl Typesetter {
 
     #[inline]
     fn should_align(&self) -> bool {
+        if !self.visual_align {
+            return false;
+        }
+
         match self.align_stack.last() {
-            Some(col) if *col <= MAX_ALIGN_COL => true,
+            Some(col) if *col <= self.max_align_col => true,
             _ => false,
         }
     }
 
     #[inline]
     fn nl_align_left(&self) -> usize {
-        minus_nf!(MAX_WIDTH, *self.align_stack.last().unwrap())
+        minus_nf!(self.max_width, *self.align_stack.last().unwrap())
     }
 
     #[inline]
     fn nl_wrap_left(&self) -> usize {
-        minus_nf!(MAX_WIDTH, self.indent.len() + WRAP_INDENT.len())
+        minus_nf!(self.max_width, self.indent_width() + self.wrap_indent_width)
     }
 
     #[inline]
@@ -259,23 +334,58 @@ impl Typesetter {
 
     #[inline]
     fn left(&self) -> usize {
-        minus_nf!(MAX_WIDTH, self.col)
+        minus_nf!(self.max_width, self.col)
     }
 
     #[inline]
     fn nl_indent_left(&self) -> usize {
-        minus_nf!(MAX_WIDTH, self.indent.len())
+        minus_nf!(self.max_width, self.indent_width())
+    }
+
+    #[inline]
+    fn indent_width(&self) -> usize {
+        if self.hard_tabs {
+            self.indent.len() * self.tab_spaces
+        } else {
+            self.indent.len()
+        }
     }
 
     #[inline]
     fn insert_wrap_indent(&mut self) {
-        self.raw_insert(WRAP_INDENT);
+        raw_insert!(self, &self.wrap_indent, self.wrap_indent_width);
     }
 
     #[inline]
     fn insert_align(&mut self) {
-        let blank = fill_str(' ', *self.align_stack.last().unwrap());
-        self.raw_insert(&blank);
+        let col = *self.align_stack.last().unwrap();
+        if self.hard_tabs && col >= self.indent_width() {
+            let blank = fill_str(' ', col - self.indent_width());
+            self.insert_indent();
+            self.raw_insert(&blank);
+        } else {
+            let blank = fill_str(' ', col);
+            self.raw_insert(&blank);
+        }
+    }
+
+    // Called after an insert at `start`.
+    #[inline]
+    fn map_text(&mut self, start: usize) {
+        let s = &self.s[start..];
+        let text_len = s.trim().len();
+        if text_len == 0 {
+            return;
+        }
+
+        let start = start + (s.len() - s.trim_start().len());
+        for node in self.nodes.iter_mut().rev() {
+            if node.1.is_some() {
+                break;
+            }
+            node.1 = Some(start);
+        }
+        self.text_end = start + text_len;
     }
 
     #[inline]
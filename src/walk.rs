@@ -0,0 +1,239 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use ignore::Match;
+use walkdir::WalkDir;
+
+use rfmt::config::{self, ConfigError};
+use rfmt::RfmtError;
+
+use crate::Opt;
+
+pub const IGNORE_FILE_NAME: &str = ".rfmtignore";
+const GIT_IGNORE_FILE_NAME: &str = ".gitignore";
+const GIT_DIR_NAME: &str = ".git";
+
+// The crate roots of a cargo package, the dirs of the other ones are searched for `*.rs` and `*/main.rs`.
+const CRATE_ROOT_FILES: [&str; 3] = ["src/lib.rs", "src/main.rs", "build.rs"];
+const CRATE_ROOT_DIRS: [&str; 4] = ["src/bin", "examples", "tests", "benches"];
+const MAIN_FILE_NAME: &str = "main.rs";
+
+// The rules which exclude a path from a dir walk. The rules of a deeper dir have precedence, like git.
+struct Ignores {
+    // `--exclude`, relative to the current dir.
+    excludes: Gitignore,
+    // The rules of the dirs from the walk root down to the current entry, with the depth of their dir.
+    dirs: Vec<(usize, Gitignore)>,
+    // The rules of the parent dirs of the walk root, the nearest first.
+    parents: Vec<Gitignore>,
+}
+
+impl Ignores {
+    fn new(cwd: &Path, excludes: &[String]) -> Ignores {
+        let mut builder = GitignoreBuilder::new(cwd);
+        for glob in excludes {
+            // Already checked by `parse_exclude`.
+            builder.add_line(None, glob).unwrap();
+        }
+        Ignores {
+            excludes: builder.build().unwrap(),
+            dirs: Vec::new(),
+            parents: Vec::new(),
+        }
+    }
+
+    // Drops the rules of the dirs which are not parents of an entry at `depth`.
+    #[inline]
+    fn leave(&mut self, depth: usize) {
+        while self.dirs.last().map_or(false, |&(dir_depth, _)| dir_depth >= depth) {
+            self.dirs.pop();
+        }
+    }
+
+    #[inline]
+    fn enter(&mut self, depth: usize, ignore: Gitignore) {
+        if !ignore.is_empty() {
+            self.dirs.push((depth, ignore));
+        }
+    }
+
+    // Returns the rule which excludes `path`, if any.
+    fn excluded(&self, path: &Path, is_dir: bool) -> Option<String> {
+        let ignores = Some(&self.excludes).into_iter()
+                                          .chain(self.dirs.iter().rev().map(|&(_, ref ignore)| ignore))
+                                          .chain(self.parents.iter());
+        for ignore in ignores {
+            match ignore.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(glob) => return Some(rule(glob)),
+                Match::Whitelist(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+// Checks an `--exclude` glob.
+pub fn parse_exclude(glob: &str) -> Result<String, String> {
+    GitignoreBuilder::new("").add_line(None, glob).map(|_| glob.to_string()).map_err(|e| e.to_string())
+}
+
+// The rust files under `root` in path order, except the ones excluded by `--exclude`, the ignore files and the
+// `ignore` of config files. The errors are in place of the files they hide.
+pub fn rs_files(root: &Path, opt: &Opt) -> Vec<Result<PathBuf, RfmtError>> {
+    let cwd = env::current_dir().unwrap_or_default();
+    let mut files = Vec::new();
+    let mut ignores = Ignores::new(&cwd, &opt.exclude);
+    let (parents, in_git) = parent_dirs(&cwd.join(root));
+    for (dir, git) in parents {
+        match dir_ignore(&dir, git) {
+            Ok(ignore) => ignores.parents.push(ignore),
+            Err(e) => files.push(Err(e)),
+        }
+    }
+    // The depth of the git repository under `root` the walk is in, if `root` is not in one.
+    let mut git_depth = None;
+
+    let mut it = WalkDir::new(root).sort_by(|a, b| a.file_name().cmp(b.file_name())).into_iter();
+    while let Some(entry) = it.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                let path = e.path().unwrap_or(root).to_path_buf();
+                files.push(Err(RfmtError::io(&path, e.into())));
+                continue;
+            },
+        };
+
+        let path = cwd.join(entry.path());
+        let is_dir = entry.file_type().is_dir();
+        let is_rs = entry.file_type().is_file() && path.extension().map_or(false, |ext| ext == "rs");
+        ignores.leave(entry.depth());
+        if git_depth.map_or(false, |depth| entry.depth() <= depth) {
+            git_depth = None;
+        }
+        // The walk root is always formatted, it is what the user asks for.
+        if entry.depth() > 0 {
+            if is_dir && entry.file_name() == GIT_DIR_NAME {
+                it.skip_current_dir();
+                continue;
+            }
+            if let Some(rule) = ignores.excluded(&path, is_dir) {
+                if opt.verbose && (is_dir || is_rs) {
+                    eprintln!("excluded {:?} by {}", entry.path(), rule);
+                }
+                if is_dir {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+        }
+
+        if is_dir {
+            if !in_git && git_depth.is_none() && path.join(GIT_DIR_NAME).exists() {
+                git_depth = Some(entry.depth());
+            }
+            match dir_ignore(&path, in_git || git_depth.is_some()) {
+                Ok(ignore) => ignores.enter(entry.depth(), ignore),
+                Err(e) => files.push(Err(e)),
+            }
+        } else if is_rs {
+            files.push(Ok(entry.into_path()));
+        }
+    }
+    files
+}
+
+// `path` itself if it is a file, otherwise the crate roots of the cargo package in `path` dir, like cargo finds them.
+pub fn crate_roots(path: &Path) -> Result<Vec<PathBuf>, RfmtError> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut roots: Vec<_> = CRATE_ROOT_FILES.iter().map(|file| path.join(file)).filter(|file| file.is_file()).collect();
+    for dir in CRATE_ROOT_DIRS.iter().map(|dir| path.join(dir)).filter(|dir| dir.is_dir()) {
+        let mut entries: Vec<_> = fs::read_dir(&dir).map_err(|e| RfmtError::io(&dir, e))?
+                                                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                                                    .collect();
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() && entry.join(MAIN_FILE_NAME).is_file() {
+                roots.push(entry.join(MAIN_FILE_NAME));
+            } else if entry.is_file() && entry.extension().map_or(false, |ext| ext == "rs") {
+                roots.push(entry);
+            }
+        }
+    }
+
+    if roots.is_empty() {
+        let e = io::Error::new(io::ErrorKind::NotFound, "no crate root found, like src/lib.rs or src/main.rs");
+        return Err(RfmtError::io(path, e));
+    }
+    Ok(roots)
+}
+
+// The parent dirs of `root`, the nearest first, and whether their .gitignore is honored, which is only in the git
+// repository of `root`. Also whether `root` is in a git repository.
+fn parent_dirs(root: &Path) -> (Vec<(PathBuf, bool)>, bool) {
+    let dirs: Vec<_> = root.ancestors().skip(1).map(Path::to_path_buf).collect();
+    let git_root = root.ancestors().position(|dir| dir.join(GIT_DIR_NAME).exists());
+    let dirs = dirs.into_iter().enumerate().map(|(idx, dir)| (dir, git_root.map_or(false, |git_root| idx < git_root)));
+    (dirs.collect(), git_root.is_some())
+}
+
+// The rules of `dir`: its .gitignore, the `ignore` of its config file, then its .rfmtignore. A later rule has
+// precedence. Like git, an invalid .gitignore pattern is skipped.
+fn dir_ignore(dir: &Path, git: bool) -> Result<Gitignore, RfmtError> {
+    let mut builder = GitignoreBuilder::new(dir);
+    if git {
+        add_ignore_file(&mut builder, &dir.join(GIT_IGNORE_FILE_NAME), false)?;
+    }
+    if let Some((config_path, patterns)) = config::load_ignore(dir)? {
+        for pattern in &patterns {
+            builder.add_line(Some(config_path.clone()), pattern).map_err(|e| ConfigError {
+                path: config_path.clone(),
+                line: 0,
+                col: 0,
+                msg: format!("invalid pattern in `ignore`: {}", e),
+            })?;
+        }
+    }
+    add_ignore_file(&mut builder, &dir.join(IGNORE_FILE_NAME), true)?;
+    builder.build().map_err(|e| RfmtError::Config(ConfigError {
+        path: dir.to_path_buf(),
+        line: 0,
+        col: 0,
+        msg: e.to_string(),
+    }))
+}
+
+fn add_ignore_file(builder: &mut GitignoreBuilder, path: &Path, strict: bool) -> Result<(), RfmtError> {
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    let src = fs::read_to_string(path).map_err(|e| RfmtError::io(path, e))?;
+    for (idx, line) in src.lines().enumerate() {
+        let result = builder.add_line(Some(path.to_path_buf()), line);
+        if let (Err(e), true) = (result, strict) {
+            return Err(RfmtError::Config(ConfigError {
+                path: path.to_path_buf(),
+                line: idx + 1,
+                col: 1,
+                msg: e.to_string(),
+            }));
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+fn rule(glob: &Glob) -> String {
+    match glob.from() {
+        Some(path) => format!("`{}` in {:?}", glob.original(), path),
+        None => format!("`--exclude {}`", glob.original()),
+    }
+}
@@ -0,0 +1,5 @@
+use std::fmt::{Debug, Display};
+
+fn f(a: u8, b: u8) -> u8 {
+    a + b
+}
@@ -0,0 +1,20 @@
+#![allow(dead_code)]
+
+extern crate log;
+
+use std::fmt::{Debug, Display};
+use std::io;
+
+mod a;
+mod b;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct A(u8,);
+
+extern "C" fn f(a: u8, b: u8) -> u8 {
+    match a {
+        0..=9 => { b },
+        _ => a, // aaaaa
+    }
+}
@@ -0,0 +1,17 @@
+#![allow(dead_code)]
+mod b;
+use std::fmt::{Display, Debug,};
+extern crate log;
+mod a;
+use std::{io};
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct A(u8,);
+
+extern fn f(a: u8, b: u8,) -> u8 {
+    match a {
+        0...9 => { b }
+        _ => a, // aaaaa
+    }
+}
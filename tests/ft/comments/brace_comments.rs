@@ -0,0 +1,21 @@
+struct A { // aaaaa
+    a: bool,
+}
+
+impl A { // bbbbb
+    fn f(&self) -> bool { // ccccc
+        match self.a { // ddddd
+            true => A { // eeeee
+                a: false,
+            }.a,
+            false => true,
+        }
+    }
+}
+
+fn g() { // TODO
+}
+
+macro_rules! m { // fffff
+    () => {};
+}
@@ -0,0 +1,22 @@
+fn f(a: bool, /* jjjjj */ b: i32, /* kkkkk */) -> bool {
+    a
+}
+
+fn g<'a, /* aaaaa */ T: Clone /* bbbbb */>(a: &'a T) {}
+
+fn h(
+    a: bool, // aaaaa
+    // bbbbb
+    b: i32 // ccccc
+) {}
+
+fn j(
+    a: bool // aaaaa
+) {}
+
+fn i<T, U>(a: T, b: U)
+where
+    T: Clone, // aaaaa
+    U: Copy // bbbbb
+{
+}
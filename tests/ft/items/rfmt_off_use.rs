@@ -0,0 +1,15 @@
+extern crate a;
+extern crate b;
+// rfmt-off
+use std::io::{Read,  Write};
+use std::fs;
+// rfmt-on
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+mod y;
+// rfmt-off
+mod  x;
+// rfmt-on
+
+fn f() {}
@@ -0,0 +1,26 @@
+// rfmt-off
+use std::io::{Read,  Write};
+use std::fs;
+// rfmt-on
+use std::collections::HashMap;
+
+fn f() {
+    let a = 1;
+    // rfmt-off
+    let b   =   2;
+    let c   =   3; // ccc
+    // rfmt-on
+    let d = 4;
+}
+
+impl A {
+    // rfmt-off
+    fn  g ( ) { }
+    // rfmt-on
+}
+
+// rfmt-on
+fn h() {
+    // rfmt-off
+    let  e  =  5;
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+#[rustfmt::skip]
+use std::io::{Read,  Write};
+use std::collections::HashMap;
+
+// aaa
+#[rustfmt::skip]
+const MATRIX: [u8; 9] = [
+    1, 0, 0,
+    0, 1, 0,
+    0, 0, 1,
+]; // bbb
+
+/// doc
+#[rfmt::skip]
+fn  f( a :u8 ) {
+    let   x=a ;   // ccc
+}
+
+struct A;
+
+impl A {
+    #[rustfmt::skip]
+    const  B : u8=1;
+
+    #[rustfmt::skip]
+    fn  g ( ) { }
+}
+
+trait T {
+    #[rustfmt::skip]
+    fn  h ( ) ;
+}
+
+fn main() {
+    #[rustfmt::skip]
+    let v = [
+        0x00,  0x01,
+        0x10,  0x11,
+    ];
+
+    match a {
+        #[rustfmt::skip]
+        1   =>   "one",
+        #[rustfmt::skip]
+        2   =>   { "two" }
+        _ => "other",
+    }
+
+    let y = #[rfmt::skip] ( 1 ,2 );
+}
@@ -0,0 +1,2 @@
+const   A: i32 = 1;
+const   B :  &str  =  "é";